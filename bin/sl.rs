@@ -1,17 +1,31 @@
-use std::io::Read;
+use std::{
+    io::{Read, Write},
+    rc::Rc,
+};
 
-use clap::{arg, Parser};
-use sl::{compiler::Compiler, grammar::ListsParser, vm::VirtualMachine};
+use clap::Parser;
+use sl::{
+    atom::Atom,
+    cli::MachineArguments,
+    compiler::{Compiler, SymbolsAndOpCodes},
+    diagnostic::Diagnostic,
+    grammar::ListsParser,
+    repl::Repl,
+    syscalls::Registry,
+    vm::VirtualMachine,
+};
 use thiserror::Error;
 
 #[derive(Parser)]
 struct Arguments {
     #[arg(short, long)]
-    file: String,
-    #[arg(short, long, default_value_t = 128)]
-    stack_size: usize,
-    #[arg(long)]
-    trace: bool,
+    file: Option<String>,
+    #[arg(short, long)]
+    print: bool,
+    #[command(flatten)]
+    machine: MachineArguments,
+    #[arg(last = true)]
+    arguments: Vec<String>,
 }
 
 #[derive(Debug, Error)]
@@ -22,8 +36,6 @@ enum Error {
     Encode(#[from] bincode::error::EncodeError),
     #[error(transparent)]
     Io(#[from] std::io::Error),
-    #[error("Parse error: {0}")]
-    Parse(String),
 }
 
 fn main() -> Result<(), Error> {
@@ -32,34 +44,145 @@ fn main() -> Result<(), Error> {
     //
     let args = Arguments::parse();
     //
+    // Build the virtual machine.
+    //
+    let mut vm = args.machine.build(args.arguments);
+    //
+    // Start the REPL if there is no source file.
+    //
+    let Some(path) = &args.file else {
+        return repl(&mut vm);
+    };
+    //
     // Open the source file.
     //
     let mut source = String::new();
-    let mut file = std::fs::File::open(&args.file)?;
+    let mut file = std::fs::File::open(path)?;
     file.read_to_string(&mut source)?;
     //
-    // Parse the source file.
+    // Compile the source file, reporting the diagnostics on failure.
     //
     let parser = ListsParser::new();
-    let atoms = parser
+    let result = parser
         .parse(&source)
-        .map_err(|v| Error::Parse(v.to_string()))?;
+        .map_err(Into::into)
+        .and_then(|v| compile(v, vm.registry()));
+    let (syms, ops, consts) = match result {
+        Ok(v) => v,
+        Err(error) => {
+            eprint!("{}", Diagnostic::new(&error, path, &source));
+            std::process::exit(1);
+        }
+    };
     //
-    // Compile the atoms.
+    // Run the binary, exiting with the requested status if the program exits.
     //
-    let mut compiler = Compiler::default();
-    compiler.lift_operators()?;
-    let (syms, ops) = compiler.compile(atoms)?;
+    let value = match vm.run(syms, ops, consts) {
+        Ok(value) => value,
+        Err(sl::error::Error::Exit(_, status)) => std::process::exit(status),
+        Err(error) => return Err(error.into()),
+    };
     //
-    // Build the virtual machine.
+    // Print the result if requested.
+    //
+    if args.print {
+        println!("{}", vm.display(&value));
+    }
     //
-    let mut vm = VirtualMachine::new(args.stack_size, args.trace);
+    // Exit with the status of the result.
     //
-    // Run the binary.
+    std::process::exit(VirtualMachine::status(&value))
+}
+
+fn compile(
+    atoms: Vec<Rc<Atom>>,
+    registry: &Registry,
+) -> Result<SymbolsAndOpCodes, sl::error::Error> {
+    let mut compiler = Compiler::default().with_registry(registry);
+    compiler.lift_operators()?;
+    compiler.compile(atoms)
+}
+
+//
+// REPL.
+//
+
+fn repl(vm: &mut VirtualMachine) -> Result<(), Error> {
+    let mut repl = Repl::new(vm.registry())?;
+    let mut input = String::new();
+    //
+    // Process the input.
     //
-    vm.run(syms, ops)?;
+    loop {
+        //
+        // Print the prompt.
+        //
+        print!("{}", if input.is_empty() { "> " } else { ". " });
+        std::io::stdout().flush()?;
+        //
+        // Read the next line, stopping at the end of the input.
+        //
+        if std::io::stdin().read_line(&mut input)? == 0 {
+            break;
+        }
+        //
+        // Wait for the parentheses to balance.
+        //
+        if depth(&input) > 0 {
+            continue;
+        }
+        //
+        // Evaluate the forms of the input.
+        //
+        match repl.eval(vm, &input, &mut std::io::stdout()) {
+            Ok(()) => (),
+            Err(sl::error::Error::Exit(_, status)) => std::process::exit(status),
+            Err(error) => eprint!("{}", Diagnostic::new(&error, "<repl>", &input)),
+        }
+        input.clear();
+    }
     //
     // Done.
     //
+    println!();
     Ok(())
 }
+
+fn depth(input: &str) -> isize {
+    let mut result = 0;
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => result += 1,
+            ')' => result -= 1,
+            //
+            // Skip character literals.
+            //
+            '^' => {
+                chars.next();
+            }
+            //
+            // Skip comments.
+            //
+            ';' => {
+                chars.by_ref().find(|v| *v == '\n');
+            }
+            //
+            // Skip strings.
+            //
+            '"' => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => break,
+                        _ => (),
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    result
+}
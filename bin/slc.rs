@@ -1,7 +1,7 @@
 use std::io::Read;
 
-use clap::{arg, Parser};
-use sl::{compiler::Compiler, grammar::ListsParser};
+use clap::Parser;
+use sl::{binary::Binary, compiler::Compiler, diagnostic::Diagnostic, grammar::ListsParser};
 use thiserror::Error;
 
 #[derive(Parser)]
@@ -10,6 +10,8 @@ struct Arguments {
     file: String,
     #[arg(short, long)]
     output: String,
+    #[arg(short = 'g', long)]
+    debug: bool,
 }
 
 #[derive(Debug, Error)]
@@ -17,11 +19,7 @@ enum Error {
     #[error(transparent)]
     Compile(#[from] sl::error::Error),
     #[error(transparent)]
-    Encode(#[from] bincode::error::EncodeError),
-    #[error(transparent)]
     Io(#[from] std::io::Error),
-    #[error("Parse error: {0}")]
-    Parse(String),
 }
 
 fn main() -> Result<(), Error> {
@@ -36,26 +34,45 @@ fn main() -> Result<(), Error> {
     let mut file = std::fs::File::open(&args.file)?;
     file.read_to_string(&mut source)?;
     //
-    // Parse the source file.
-    //
-    let parser = ListsParser::new();
-    let atoms = parser
-        .parse(&source)
-        .map_err(|v| Error::Parse(v.to_string()))?;
-    //
-    // Compile the atoms.
+    // Compile the source file, reporting the diagnostics on failure.
     //
-    let mut compiler = Compiler::default();
-    compiler.lift_operators()?;
-    let state = compiler.compile(atoms)?;
+    let state = match compile(&args.file, &source, args.debug) {
+        Ok(v) => v,
+        Err(error) => {
+            eprint!("{}", Diagnostic::new(&error, &args.file, &source));
+            std::process::exit(1);
+        }
+    };
     //
     // Write the serialize output.
     //
-    let conf = bincode::config::standard();
     let mut file = std::fs::File::create(&args.output)?;
-    bincode::encode_into_std_write(state, &mut file, conf)?;
+    state.encode(&mut file)?;
     //
     // Done.
     //
     Ok(())
 }
+
+fn compile(path: &str, source: &str, debug: bool) -> Result<Binary, sl::error::Error> {
+    //
+    // Parse the source file.
+    //
+    let parser = ListsParser::new();
+    let atoms = parser.parse(source)?;
+    //
+    // Compile the atoms.
+    //
+    let mut compiler = Compiler::default().with_source(path, source);
+    compiler.lift_operators()?;
+    let ((syms, ops, consts), info) = compiler.compile_with_debug_info(atoms)?;
+    let binary = Binary::new(syms, ops, consts);
+    //
+    // Keep the debug information if requested.
+    //
+    Ok(if debug {
+        binary.with_debug_info(info)
+    } else {
+        binary
+    })
+}
@@ -1,5 +1,8 @@
-use clap::{arg, Parser};
-use sl::compiler::SymbolsAndOpCodes;
+use std::collections::HashMap;
+
+use clap::Parser;
+use num_bigint::BigInt;
+use sl::{binary::Binary, opcodes::Constant};
 use thiserror::Error;
 
 #[derive(Parser)]
@@ -17,8 +20,6 @@ enum Error {
     #[error(transparent)]
     Mnml(#[from] sl::error::Error),
     #[error(transparent)]
-    Decode(#[from] bincode::error::DecodeError),
-    #[error(transparent)]
     Io(#[from] std::io::Error),
 }
 
@@ -34,18 +35,73 @@ fn main() -> Result<(), Error> {
     //
     // Decode the bytecode file.
     //
-    let conf = bincode::config::standard();
-    let (syms, ops): SymbolsAndOpCodes = bincode::decode_from_std_read(&mut file, conf)?;
+    let Binary {
+        symbols: syms,
+        opcodes: ops,
+        constants,
+        debug,
+    } = Binary::decode(&mut file)?;
     //
     // Dump the binary.
     //
+    let mut sources = HashMap::new();
+    let mut line = None;
     ops.iter().enumerate().for_each(|(i, op)| {
         if let Some((e, _, _)) = syms.iter().find(|(_, n, _)| *n == i) {
             println!("{e}:");
+            line = None;
+            //
+            // Print the origin of lambdas.
+            //
+            if let Some(v) = debug.as_ref().and_then(|v| v.lambda(e)) {
+                println!("  ; in {}: {}", v.parent, v.text);
+            }
+        }
+        if let Some(debug) = &debug {
+            //
+            // Print the source line when it changes.
+            //
+            if let Some(loc) = debug.location(i)
+                && line != Some((loc.file, loc.line))
+            {
+                let path = debug.file(loc.file).unwrap_or("?");
+                let lines = sources.entry(loc.file).or_insert_with(|| {
+                    std::fs::read_to_string(path)
+                        .map(|v| v.lines().map(str::to_owned).collect())
+                        .unwrap_or_else(|_| Vec::new())
+                });
+                let text = lines.get(loc.line - 1).map(|v| v.trim()).unwrap_or("");
+                println!("  ; {path}:{}:{} {text}", loc.line, loc.column);
+                line = Some((loc.file, loc.line));
+            }
+            //
+            // Print the bindings when the scope changes.
+            //
+            if let Some((_, scope)) = debug.scopes().iter().rev().find(|(n, _)| *n == i)
+                && !scope.is_empty()
+            {
+                let names: Vec<_> = scope.iter().map(|(k, v)| format!("{k}@{v}")).collect();
+                println!("  ; locals: {}", names.join(" "));
+            }
         }
         println!("    {i:04} {op:?}");
     });
     //
+    // Dump the constant pool.
+    //
+    if !constants.is_empty() {
+        println!("<constants>:");
+        constants.iter().enumerate().for_each(|(i, v)| match v {
+            Constant::BigNum(v) => {
+                println!("    {i:04} BigNum({})", BigInt::from_signed_bytes_le(v))
+            }
+            Constant::String(v) => {
+                println!("    {i:04} String({:?})", String::from_utf8_lossy(v))
+            }
+            v => println!("    {i:04} {v:?}"),
+        });
+    }
+    //
     // Done.
     //
     Ok(())
@@ -0,0 +1,438 @@
+use std::{
+    collections::BTreeSet,
+    io::{Read, Write},
+    rc::Rc,
+};
+
+use clap::Parser;
+use sl::{
+    atom::Atom,
+    compiler::{Compiler, SymbolsAndOpCodes},
+    debug::DebugInfo,
+    diagnostic::Diagnostic,
+    grammar::ListsParser,
+    opcodes::{Arity, Constant, OpCode},
+    stack::Value,
+    syscalls::Registry,
+    vm::{Overflow, Status, VirtualMachine},
+};
+use thiserror::Error;
+
+#[derive(Parser)]
+struct Arguments {
+    #[arg(short, long)]
+    file: String,
+    #[arg(short, long, default_value_t = 128)]
+    stack_size: usize,
+    #[arg(long)]
+    bignum: bool,
+    #[arg(last = true)]
+    arguments: Vec<String>,
+}
+
+#[derive(Debug, Error)]
+enum Error {
+    #[error(transparent)]
+    Mnml(#[from] sl::error::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+fn main() -> Result<(), Error> {
+    //
+    // Parse the arguments.
+    //
+    let args = Arguments::parse();
+    //
+    // Build the virtual machine.
+    //
+    let overflow = if args.bignum {
+        Overflow::Promote
+    } else {
+        Overflow::Error
+    };
+    let vm = VirtualMachine::new(args.stack_size, false)
+        .with_arguments(args.arguments)
+        .with_overflow(overflow);
+    //
+    // Open the source file.
+    //
+    let mut source = String::new();
+    let mut file = std::fs::File::open(&args.file)?;
+    file.read_to_string(&mut source)?;
+    //
+    // Compile the source file, reporting the diagnostics on failure.
+    //
+    let parser = ListsParser::new();
+    let result = parser
+        .parse(&source)
+        .map_err(Into::into)
+        .and_then(|v| compile(v, &args.file, &source, vm.registry()));
+    let ((syms, ops, consts), debug) = match result {
+        Ok(v) => v,
+        Err(error) => {
+            eprint!("{}", Diagnostic::new(&error, &args.file, &source));
+            std::process::exit(1);
+        }
+    };
+    //
+    // Start the debugger.
+    //
+    let mut debugger = Debugger::new(vm, syms, ops, consts, debug);
+    debugger.restart();
+    debugger.repl()
+}
+
+fn compile(
+    atoms: Vec<Rc<Atom>>,
+    path: &str,
+    source: &str,
+    registry: &Registry,
+) -> Result<(SymbolsAndOpCodes, DebugInfo), sl::error::Error> {
+    let mut compiler = Compiler::default()
+        .with_registry(registry)
+        .with_source(path, source);
+    compiler.lift_operators()?;
+    compiler.compile_with_debug_info(atoms)
+}
+
+//
+// Debugger.
+//
+
+struct Debugger {
+    breakpoints: BTreeSet<usize>,
+    consts: Vec<Constant>,
+    debug: DebugInfo,
+    ops: Vec<OpCode>,
+    syms: Vec<(Box<str>, usize, Arity)>,
+    vm: VirtualMachine,
+}
+
+impl Debugger {
+    fn new(
+        vm: VirtualMachine,
+        mut syms: Vec<(Box<str>, usize, Arity)>,
+        ops: Vec<OpCode>,
+        consts: Vec<Constant>,
+        debug: DebugInfo,
+    ) -> Self {
+        syms.sort_by_key(|(_, v, _)| *v);
+        Self {
+            breakpoints: BTreeSet::new(),
+            consts,
+            debug,
+            ops,
+            syms,
+            vm,
+        }
+    }
+
+    fn repl(&mut self) -> Result<(), Error> {
+        let mut line = String::new();
+        loop {
+            //
+            // Print the prompt.
+            //
+            print!("(sldbg) ");
+            std::io::stdout().flush()?;
+            //
+            // Read the next command, stopping at the end of the input.
+            //
+            line.clear();
+            if std::io::stdin().read_line(&mut line)? == 0 {
+                break;
+            }
+            let mut words = line.split_whitespace();
+            let Some(command) = words.next() else {
+                continue;
+            };
+            let argument = words.next();
+            //
+            // Execute the command.
+            //
+            match (command, argument) {
+                ("b" | "break", Some(v)) => self.set_breakpoint(v),
+                ("d" | "delete", Some(v)) => self.delete_breakpoint(v),
+                ("s" | "step", v) => match v.map(str::parse).unwrap_or(Ok(1)) {
+                    Ok(n) => self.step(n, false),
+                    Err(_) => println!("Invalid step count"),
+                },
+                ("n" | "next", None) => self.step(1, true),
+                ("si" | "stepi", v) => match v.map(str::parse).unwrap_or(Ok(1)) {
+                    Ok(n) => {
+                        let mut count = 0;
+                        self.run_until(|_| {
+                            count += 1;
+                            count >= n
+                        });
+                    }
+                    Err(_) => println!("Invalid step count"),
+                },
+                ("f" | "finish", None) => {
+                    let depth = self.depth();
+                    self.run_until(|this| this.depth() < depth);
+                }
+                ("c" | "continue", None) => self.run_until(|_| false),
+                ("bt" | "backtrace", None) => self.backtrace(),
+                ("l" | "locals", None) => self.locals(),
+                ("x" | "stack", None) => self.stack(),
+                ("r" | "restart", None) => self.restart(),
+                ("h" | "help", None) => help(),
+                ("q" | "quit", None) => break,
+                _ => println!("Invalid command, type 'help' for a list of commands"),
+            }
+        }
+        //
+        // Done.
+        //
+        println!();
+        Ok(())
+    }
+
+    //
+    // Execution.
+    //
+
+    fn restart(&mut self) {
+        let syms = self.syms.clone();
+        let ops = self.ops.clone();
+        let consts = self.consts.clone();
+        match self.vm.load(syms, ops, consts) {
+            Ok(()) => self.location(),
+            Err(error) => println!("Error: {error}"),
+        }
+    }
+
+    fn step(&mut self, count: usize, over: bool) {
+        let depth = self.depth();
+        //
+        // Without source locations, step over instructions.
+        //
+        if !self.debug.has_locations() {
+            let mut steps = 0;
+            self.run_until(|this| {
+                if over && this.depth() > depth {
+                    return false;
+                }
+                steps += 1;
+                steps >= count
+            });
+            return;
+        }
+        //
+        // Otherwise, run until the source line changes, staying in the
+        // current frame or its callers when stepping over calls.
+        //
+        let line = |this: &Self| {
+            this.vm
+                .pc()
+                .and_then(|pc| this.debug.location(pc))
+                .map(|v| (v.file, v.line))
+        };
+        let mut from = line(self);
+        let mut steps = 0;
+        self.run_until(|this| {
+            if over && this.depth() > depth {
+                return false;
+            }
+            match line(this) {
+                Some(v) if Some(v) != from => {
+                    from = Some(v);
+                    steps += 1;
+                    steps >= count
+                }
+                _ => false,
+            }
+        });
+    }
+
+    fn run_until(&mut self, mut done: impl FnMut(&Self) -> bool) {
+        if self.vm.pc().is_none() {
+            println!("The program is not running, type 'restart' to run it again");
+            return;
+        }
+        loop {
+            //
+            // Execute the next instruction.
+            //
+            match self.vm.step(1) {
+                Ok(Status::Running) => (),
+                Ok(Status::Finished(v)) => {
+                    println!("Finished: {}", self.vm.display(&v));
+                    return;
+                }
+                Ok(Status::Yielded(v)) => {
+                    println!("Yielded: {}", self.vm.display(&v));
+                    break;
+                }
+                Err(error) => {
+                    println!("Error: {error}");
+                    return;
+                }
+            }
+            //
+            // Stop at the breakpoints.
+            //
+            if let Some(pc) = self.vm.pc()
+                && self.breakpoints.contains(&pc)
+            {
+                println!("Breakpoint at {pc:04}");
+                break;
+            }
+            //
+            // Stop when the condition is met.
+            //
+            if done(self) {
+                break;
+            }
+        }
+        self.location();
+    }
+
+    //
+    // Breakpoints.
+    //
+
+    fn address(&self, target: &str) -> Option<usize> {
+        target.parse().ok().or_else(|| {
+            self.syms
+                .iter()
+                .find_map(|(k, v, _)| (k.as_ref() == target).then_some(*v))
+        })
+    }
+
+    fn set_breakpoint(&mut self, target: &str) {
+        match self.address(target) {
+            Some(pc) if pc < self.ops.len() => {
+                self.breakpoints.insert(pc);
+                println!("Breakpoint at {}", self.describe(pc));
+            }
+            _ => println!("Unknown function or address: {target}"),
+        }
+    }
+
+    fn delete_breakpoint(&mut self, target: &str) {
+        match self.address(target) {
+            Some(pc) if self.breakpoints.remove(&pc) => println!("Deleted breakpoint at {pc:04}"),
+            _ => println!("No breakpoint at {target}"),
+        }
+    }
+
+    //
+    // Inspection.
+    //
+
+    fn depth(&self) -> usize {
+        self.vm
+            .stack()
+            .iter()
+            .filter(|v| matches!(v, Value::Link(_)))
+            .count()
+    }
+
+    fn describe(&self, pc: usize) -> String {
+        //
+        // Find the function containing the address.
+        //
+        let index = self.syms.partition_point(|(_, v, _)| *v <= pc);
+        let function = match index {
+            0 => "??".to_owned(),
+            n => {
+                let (name, addr, _) = &self.syms[n - 1];
+                format!("{name}+{}", pc - addr)
+            }
+        };
+        //
+        // Format the location, with its source position if known.
+        //
+        match self.debug.location(pc) {
+            Some(v) => {
+                let path = self.debug.file(v.file).unwrap_or("?");
+                format!("{pc:04} <{function}> at {path}:{}:{}", v.line, v.column)
+            }
+            None => format!("{pc:04} <{function}>"),
+        }
+    }
+
+    fn location(&self) {
+        if let Some(pc) = self.vm.pc() {
+            println!("{} {:?}", self.describe(pc), self.ops[pc]);
+        }
+    }
+
+    fn backtrace(&self) {
+        let Some(pc) = self.vm.pc() else {
+            println!("The program is not running");
+            return;
+        };
+        //
+        // The return addresses point past the calls of the callers.
+        //
+        let callers = self.vm.stack().iter().rev().filter_map(|v| match v {
+            Value::Link(v) if *v < self.ops.len() => Some(*v - 1),
+            _ => None,
+        });
+        //
+        // Print the frames.
+        //
+        std::iter::once(pc)
+            .chain(callers)
+            .enumerate()
+            .for_each(|(i, v)| println!("#{i} {}", self.describe(v)));
+    }
+
+    fn locals(&self) {
+        let Some(pc) = self.vm.pc() else {
+            println!("The program is not running");
+            return;
+        };
+        //
+        // Compute the base of the frame.
+        //
+        let stack = self.vm.stack();
+        let Some(base) = self
+            .debug
+            .depth(pc)
+            .and_then(|v| stack.len().checked_sub(v))
+        else {
+            println!("No debug information at {pc:04}");
+            return;
+        };
+        //
+        // Print the bindings.
+        //
+        self.debug.locals(pc).iter().for_each(|(name, slot)| {
+            if let Some(value) = stack.iter().nth(base + slot) {
+                println!("{name} = {}", self.vm.display(value));
+            }
+        });
+    }
+
+    fn stack(&self) {
+        self.vm
+            .stack()
+            .iter()
+            .enumerate()
+            .rev()
+            .for_each(|(i, v)| match v {
+                Value::Link(v) => println!("{i:04} #<link {v:04}>"),
+                v => println!("{i:04} {}", self.vm.display(v)),
+            });
+    }
+}
+
+fn help() {
+    println!("break <function|address>   set a breakpoint");
+    println!("delete <function|address>  delete a breakpoint");
+    println!("step [count]               run to the next source lines");
+    println!("next                       run to the next source line, stepping over calls");
+    println!("stepi [count]              execute the next instructions");
+    println!("finish                     run until the current function returns");
+    println!("continue                   run until the next breakpoint");
+    println!("backtrace                  print the call frames");
+    println!("locals                     print the arguments and bindings in scope");
+    println!("stack                      print the content of the stack");
+    println!("restart                    restart the program");
+    println!("quit                       exit the debugger");
+}
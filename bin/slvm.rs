@@ -1,15 +1,17 @@
-use clap::{arg, Parser};
-use sl::{compiler::SymbolsAndOpCodes, vm::VirtualMachine};
+use clap::Parser;
+use sl::{binary::Binary, cli::MachineArguments, vm::VirtualMachine};
 use thiserror::Error;
 
 #[derive(Parser)]
 struct Arguments {
     #[arg(short, long)]
     file: String,
-    #[arg(short, long, default_value_t = 128)]
-    stack_size: usize,
-    #[arg(long)]
-    trace: bool,
+    #[arg(short, long)]
+    print: bool,
+    #[command(flatten)]
+    machine: MachineArguments,
+    #[arg(last = true)]
+    arguments: Vec<String>,
 }
 
 #[derive(Debug, Error)]
@@ -17,8 +19,6 @@ enum Error {
     #[error(transparent)]
     Mnml(#[from] sl::error::Error),
     #[error(transparent)]
-    Decode(#[from] bincode::error::DecodeError),
-    #[error(transparent)]
     Io(#[from] std::io::Error),
 }
 
@@ -34,18 +34,27 @@ fn main() -> Result<(), Error> {
     //
     // Decode the bytecode file.
     //
-    let conf = bincode::config::standard();
-    let (syms, ops): SymbolsAndOpCodes = bincode::decode_from_std_read(&mut file, conf)?;
+    let binary = Binary::decode(&mut file)?;
     //
     // Build the virtual machine.
     //
-    let mut vm = VirtualMachine::new(args.stack_size, args.trace);
+    let mut vm = args.machine.build(args.arguments);
+    //
+    // Run the binary, exiting with the requested status if the program exits.
+    //
+    let value = match vm.run(binary.symbols, binary.opcodes, binary.constants) {
+        Ok(value) => value,
+        Err(sl::error::Error::Exit(_, status)) => std::process::exit(status),
+        Err(error) => return Err(error.into()),
+    };
     //
-    // Run the binary.
+    // Print the result if requested.
     //
-    vm.run(syms, ops)?;
+    if args.print {
+        println!("{}", vm.display(&value));
+    }
     //
-    // Done.
+    // Exit with the status of the result.
     //
-    Ok(())
+    std::process::exit(VirtualMachine::status(&value))
 }
@@ -1,31 +1,59 @@
 use std::rc::Rc;
 
+use num_bigint::BigInt;
+
+use crate::error::Error;
+
+//
+// Span.
+//
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub const fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    pub fn join(self, other: Span) -> Self {
+        Self::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
 //
 // Atom.
 //
 
 pub enum Atom {
-    Nil,
-    True,
-    Char(u8),
-    Number(i64),
-    Pair(Rc<Atom>, Rc<Atom>),
-    String(Box<str>),
-    Symbol(Box<str>),
-    Wildcard,
+    Nil(Span),
+    True(Span),
+    Char(char, Span),
+    Number(i64, Span),
+    BigNum(BigInt, Span),
+    Float(f64, Span),
+    Pair(Rc<Atom>, Rc<Atom>, Span),
+    String(Box<str>, Span),
+    Symbol(Box<str>, Span),
+    Wildcard(Span),
 }
 
 impl std::fmt::Debug for Atom {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Atom::Nil => write!(f, "nil"),
-            Atom::True => write!(f, "t"),
-            Atom::Char(v) => write!(f, "char({v})"),
-            Atom::Number(v) => write!(f, "number({v})"),
-            Atom::Pair(a, b) => write!(f, "({a:?} {b:?})"),
-            Atom::String(v) => write!(f, "string({v})"),
-            Atom::Symbol(v) => write!(f, "symbol({v})"),
-            Atom::Wildcard => write!(f, "_"),
+            Atom::Nil(_) => write!(f, "nil"),
+            Atom::True(_) => write!(f, "t"),
+            Atom::Char(v, _) => write!(f, "char({})", *v as u32),
+            Atom::Number(v, _) => write!(f, "number({v})"),
+            Atom::BigNum(v, _) => write!(f, "bignum({v})"),
+            Atom::Float(v, _) => write!(f, "float({v})"),
+            Atom::Pair(a, b, _) => write!(f, "({a:?} {b:?})"),
+            Atom::String(v, _) => write!(f, "string({v})"),
+            Atom::Symbol(v, _) => write!(f, "symbol({v})"),
+            Atom::Wildcard(_) => write!(f, "_"),
         }
     }
 }
@@ -35,61 +63,121 @@ impl std::fmt::Debug for Atom {
 //
 
 impl Atom {
-    pub fn char(v: u8) -> Rc<Self> {
-        Self::Char(v).into()
+    pub fn char(v: char, span: Span) -> Rc<Self> {
+        Self::Char(v, span).into()
     }
 
-    pub fn nil() -> Rc<Atom> {
-        Self::Nil.into()
+    pub fn float(v: f64, span: Span) -> Rc<Atom> {
+        Self::Float(v, span).into()
     }
 
-    pub fn number(v: i64) -> Rc<Atom> {
-        Self::Number(v).into()
+    pub fn nil(span: Span) -> Rc<Atom> {
+        Self::Nil(span).into()
     }
 
-    pub fn string(v: &str) -> Rc<Atom> {
-        let mut result = String::new();
+    pub fn number(v: i64, span: Span) -> Rc<Atom> {
+        Self::Number(v, span).into()
+    }
+
+    pub fn integer(v: &str, span: Span) -> Result<Rc<Atom>, Error> {
+        /*
+         * Promote the literals that do not fit in a fixed-width integer.
+         */
+        match v.parse() {
+            Ok(v) => Ok(Self::number(v, span)),
+            Err(_) => v
+                .parse()
+                .map(|v| Self::BigNum(v, span).into())
+                .map_err(|_| Error::Parse(format!("Invalid integer literal: {v}")).at(span)),
+        }
+    }
+
+    pub fn string(v: &str, span: Span) -> Option<Rc<Atom>> {
         /*
          * Trim the double quotes.
          */
-        let v = v.trim_matches('"');
+        let v = &v[1..v.len() - 1];
         /*
          * Parse the escape sequences.
          */
+        let result = Self::unescape(v)?;
+        /*
+         * Done.
+         */
+        Some(Self::String(result.into_boxed_str(), span).into())
+    }
+
+    pub fn unescape(v: &str) -> Option<String> {
+        let mut result = String::new();
         let mut chars = v.chars();
         while let Some(c) = chars.next() {
-            if c == '\\' {
-                match chars.next() {
-                    Some('0') => result.push('\0'),
-                    Some('e') => result.push('\x1B'),
-                    Some('n') => result.push('\n'),
-                    Some('r') => result.push('\r'),
-                    Some('t') => result.push('\t'),
-                    Some('"') => result.push('"'),
-                    Some('\\') => result.push('\\'),
-                    Some(c) => result.push(c), // Handle invalid escape
-                    None => break,
-                }
-            } else {
+            if c != '\\' {
                 result.push(c);
+                continue;
             }
+            let c = match chars.next()? {
+                '0' => '\0',
+                'e' => '\x1B',
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                /*
+                 * Unicode escapes must be valid scalar values.
+                 */
+                'u' => {
+                    let rem = chars.as_str().strip_prefix('{')?;
+                    let (digits, rem) = rem.split_once('}')?;
+                    let c = u32::from_str_radix(digits, 16)
+                        .ok()
+                        .and_then(char::from_u32)?;
+                    chars = rem.chars();
+                    c
+                }
+                c => c, // Handle invalid escape
+            };
+            result.push(c);
         }
-        /*
-         * Done.
-         */
-        Self::String(result.into_boxed_str()).into()
+        Some(result)
     }
 
-    pub fn symbol(v: &str) -> Rc<Atom> {
-        Self::Symbol(v.into()).into()
+    pub fn symbol(v: &str, span: Span) -> Rc<Atom> {
+        Self::Symbol(v.into(), span).into()
     }
 
-    pub fn t() -> Rc<Atom> {
-        Self::True.into()
+    pub fn t(span: Span) -> Rc<Atom> {
+        Self::True(span).into()
     }
 
-    pub fn wildcard() -> Rc<Atom> {
-        Self::Wildcard.into()
+    pub fn wildcard(span: Span) -> Rc<Atom> {
+        Self::Wildcard(span).into()
+    }
+}
+
+//
+// Span.
+//
+
+impl Atom {
+    pub const fn span(&self) -> Span {
+        match self {
+            Atom::Nil(span)
+            | Atom::True(span)
+            | Atom::Char(_, span)
+            | Atom::Number(_, span)
+            | Atom::BigNum(_, span)
+            | Atom::Float(_, span)
+            | Atom::Pair(_, _, span)
+            | Atom::String(_, span)
+            | Atom::Symbol(_, span)
+            | Atom::Wildcard(span) => *span,
+        }
+    }
+
+    pub fn with_span(self: Rc<Atom>, span: Span) -> Rc<Atom> {
+        match self.as_ref() {
+            Atom::Pair(car, cdr, _) => Self::Pair(car.clone(), cdr.clone(), span).into(),
+            _ => self,
+        }
     }
 }
 
@@ -99,7 +187,7 @@ impl Atom {
 
 impl Atom {
     pub const fn is_nil(&self) -> bool {
-        matches!(self, Atom::Nil)
+        matches!(self, Atom::Nil(_))
     }
 
     pub const fn is_pair(&self) -> bool {
@@ -114,14 +202,15 @@ impl Atom {
 impl Atom {
     pub fn conc(a: Rc<Atom>, b: Rc<Atom>) -> Rc<Atom> {
         match a.as_ref() {
-            Atom::Nil => b,
-            Atom::Pair(car, cdr) => Atom::cons(car.clone(), Atom::conc(cdr.clone(), b)),
+            Atom::Nil(_) => b,
+            Atom::Pair(car, cdr, _) => Atom::cons(car.clone(), Atom::conc(cdr.clone(), b)),
             _ => Atom::cons(a, b),
         }
     }
 
     pub fn cons(a: Rc<Atom>, b: Rc<Atom>) -> Rc<Atom> {
-        Self::Pair(a, b).into()
+        let span = a.span().join(b.span());
+        Self::Pair(a, b, span).into()
     }
 
     pub fn iter(self: &Rc<Atom>) -> impl std::iter::Iterator<Item = Rc<Atom>> {
@@ -145,7 +234,7 @@ impl std::iter::Iterator for AtomIterator {
 
     fn next(&mut self) -> Option<Self::Item> {
         match self.0.as_ref() {
-            Atom::Pair(car, cdr) => {
+            Atom::Pair(car, cdr, _) => {
                 let value = car.clone();
                 self.0 = cdr.clone();
                 Some(value)
@@ -0,0 +1,250 @@
+use std::io::{Read, Write};
+
+use bincode::{Decode, Encode};
+use strum_macros::{Display, EnumString};
+
+use crate::{
+    debug::DebugInfo,
+    error::Error,
+    opcodes::{Arity, Constant, Constants, Immediate, OpCode, OpCodes},
+};
+
+//
+// Format identification.
+//
+
+const MAGIC: [u8; 4] = *b"\0sl\0";
+
+pub const FORMAT_VERSION: u32 = 1;
+
+//
+// Header.
+//
+
+#[derive(Debug, Encode, Decode)]
+struct Header {
+    format: u32,
+    checksum: u64,
+}
+
+//
+// Section.
+//
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Display, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum Section {
+    Symbols,
+    Code,
+    Constants,
+    Debug,
+}
+
+//
+// Binary.
+//
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Binary {
+    pub symbols: Vec<(Box<str>, usize, Arity)>,
+    pub opcodes: OpCodes,
+    pub constants: Constants,
+    pub debug: Option<DebugInfo>,
+}
+
+impl Binary {
+    pub fn new(
+        symbols: Vec<(Box<str>, usize, Arity)>,
+        opcodes: OpCodes,
+        constants: Constants,
+    ) -> Self {
+        Self {
+            symbols,
+            opcodes,
+            constants,
+            debug: None,
+        }
+    }
+
+    pub fn with_debug_info(mut self, debug: DebugInfo) -> Self {
+        self.debug = Some(debug);
+        self
+    }
+
+    pub fn encode(&self, writer: &mut impl Write) -> Result<(), Error> {
+        let conf = bincode::config::standard();
+        //
+        // Encode the sections.
+        //
+        let mut sections = vec![
+            (
+                Section::Symbols,
+                bincode::encode_to_vec(&self.symbols, conf)?,
+            ),
+            (Section::Code, bincode::encode_to_vec(&self.opcodes, conf)?),
+            (
+                Section::Constants,
+                bincode::encode_to_vec(&self.constants, conf)?,
+            ),
+        ];
+        if let Some(debug) = &self.debug {
+            sections.push((Section::Debug, bincode::encode_to_vec(debug, conf)?));
+        }
+        let sections: Vec<_> = sections
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect();
+        let payload = bincode::encode_to_vec(sections, conf)?;
+        //
+        // Encode the header.
+        //
+        let header = Header {
+            format: FORMAT_VERSION,
+            checksum: checksum(&payload),
+        };
+        let header = bincode::encode_to_vec(header, conf)?;
+        //
+        // Write the container.
+        //
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&header)?;
+        writer.write_all(&payload)?;
+        //
+        // Done.
+        //
+        Ok(())
+    }
+
+    pub fn decode(reader: &mut impl Read) -> Result<Self, Error> {
+        let conf = bincode::config::standard();
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        //
+        // Check the magic number.
+        //
+        let Some(bytes) = bytes.strip_prefix(&MAGIC) else {
+            return Err(Error::InvalidBinary("bad magic number".into()));
+        };
+        //
+        // Decode the header and check the format version.
+        //
+        let (header, len): (Header, _) = bincode::decode_from_slice(bytes, conf)?;
+        if header.format != FORMAT_VERSION {
+            return Err(Error::IncompatibleBinary(header.format));
+        }
+        //
+        // Verify the payload.
+        //
+        let payload = &bytes[len..];
+        if checksum(payload) != header.checksum {
+            return Err(Error::CorruptedBinary);
+        }
+        //
+        // Decode the sections, skipping the unknown ones.
+        //
+        let (sections, _): (Vec<(String, Vec<u8>)>, _) = bincode::decode_from_slice(payload, conf)?;
+        let mut result = Binary::default();
+        let mut found = Vec::new();
+        sections.into_iter().try_for_each(|(k, v)| {
+            let Ok(section) = k.parse::<Section>() else {
+                return Ok::<_, Error>(());
+            };
+            match section {
+                Section::Symbols => result.symbols = bincode::decode_from_slice(&v, conf)?.0,
+                Section::Code => result.opcodes = bincode::decode_from_slice(&v, conf)?.0,
+                Section::Constants => result.constants = bincode::decode_from_slice(&v, conf)?.0,
+                Section::Debug => result.debug = Some(bincode::decode_from_slice(&v, conf)?.0),
+            }
+            found.push(section);
+            Ok(())
+        })?;
+        //
+        // Make sure the mandatory sections are present.
+        //
+        if let Some(v) = [Section::Symbols, Section::Code, Section::Constants]
+            .into_iter()
+            .find(|v| !found.contains(v))
+        {
+            return Err(Error::MissingSection(v.to_string().into()));
+        }
+        //
+        // Make sure the sections are consistent.
+        //
+        result.validate()?;
+        Ok(result)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let invalid = |v: String| Error::InvalidBinary(v.into());
+        let len = self.opcodes.len();
+        //
+        // Functions start within the code.
+        //
+        if let Some((name, addr, _)) = self.symbols.iter().find(|(_, v, _)| *v >= len) {
+            return Err(invalid(format!("invalid address {addr} for {name}")));
+        }
+        //
+        // Funcalls reference a function with the same arity.
+        //
+        let funcall = |addr: u32, arity: Arity| {
+            self.symbols
+                .iter()
+                .any(|(_, v, w)| *v == addr as usize && *w == arity)
+        };
+        //
+        // Pairs only reference earlier cells. Symbol identifiers only exist at
+        // runtime.
+        //
+        self.constants
+            .iter()
+            .enumerate()
+            .try_for_each(|(i, v)| match v {
+                Constant::Pair(car, cdr) if *car as usize >= i || *cdr as usize >= i => {
+                    Err(invalid(format!("invalid pair in constant {i}")))
+                }
+                Constant::Immediate(Immediate::Funcall(addr, arity)) if !funcall(*addr, *arity) => {
+                    Err(invalid(format!("invalid funcall in constant {i}")))
+                }
+                Constant::Immediate(Immediate::Symbol(_)) => {
+                    Err(invalid(format!("invalid symbol in constant {i}")))
+                }
+                _ => Ok(()),
+            })?;
+        //
+        // Loads reference the constant pool, branches land within the code or
+        // at its end, and stack lookups skip at least one value. The stack
+        // discipline of the code is not verified and is checked at runtime.
+        //
+        self.opcodes
+            .iter()
+            .enumerate()
+            .try_for_each(|(i, v)| match v {
+                OpCode::Br(n) | OpCode::Brn(n) | OpCode::Try(n)
+                    if !(0..=len as isize).contains(&(i as isize + n)) =>
+                {
+                    Err(invalid(format!("invalid branch target at {i:04}")))
+                }
+                OpCode::Get(0) => Err(invalid(format!("invalid stack lookup at {i:04}"))),
+                OpCode::Ldc(n) if *n >= self.constants.len() => {
+                    Err(invalid(format!("invalid constant {n} at {i:04}")))
+                }
+                OpCode::Psh(Immediate::Funcall(addr, arity)) if !funcall(*addr, *arity) => {
+                    Err(invalid(format!("invalid funcall at {i:04}")))
+                }
+                OpCode::Psh(Immediate::Symbol(_)) => {
+                    Err(invalid(format!("invalid symbol at {i:04}")))
+                }
+                _ => Ok(()),
+            })
+    }
+}
+
+//
+// FNV-1a checksum.
+//
+
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |acc, v| {
+        (acc ^ *v as u64).wrapping_mul(0x100000001b3)
+    })
+}
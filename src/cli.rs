@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use clap::Args;
+
+use crate::{
+    syscalls::Policy,
+    vm::{Overflow, VirtualMachine},
+};
+
+//
+// Machine arguments.
+//
+// The command line options shared by the binaries that run programs.
+//
+
+#[derive(Args)]
+pub struct MachineArguments {
+    #[arg(short, long, default_value_t = 128)]
+    pub stack_size: usize,
+    #[arg(long)]
+    pub trace: bool,
+    #[arg(long)]
+    pub bignum: bool,
+    #[arg(long)]
+    pub sandbox: bool,
+    #[arg(long)]
+    pub allow: Vec<String>,
+    #[arg(long)]
+    pub deny: Vec<String>,
+    #[arg(long)]
+    pub fuel: Option<usize>,
+    #[arg(long)]
+    pub max_depth: Option<usize>,
+    #[arg(long)]
+    pub timeout: Option<u64>,
+}
+
+impl MachineArguments {
+    pub fn build(&self, arguments: Vec<String>) -> VirtualMachine {
+        //
+        // Select the overflow behavior.
+        //
+        let overflow = if self.bignum {
+            Overflow::Promote
+        } else {
+            Overflow::Error
+        };
+        //
+        // Build the system call policy, the denials taking precedence.
+        //
+        let policy = if self.sandbox {
+            Policy::deny_all()
+        } else {
+            Policy::allow_all()
+        };
+        let policy = self.allow.iter().fold(policy, |acc, v| acc.allow(v));
+        let policy = self.deny.iter().fold(policy, |acc, v| acc.deny(v));
+        //
+        // Build the virtual machine.
+        //
+        let mut vm = VirtualMachine::new(self.stack_size, self.trace)
+            .with_arguments(arguments)
+            .with_overflow(overflow)
+            .with_policy(policy);
+        if let Some(fuel) = self.fuel {
+            vm = vm.with_fuel(fuel);
+        }
+        if let Some(depth) = self.max_depth {
+            vm = vm.with_max_depth(depth);
+        }
+        if let Some(timeout) = self.timeout {
+            vm = vm.with_timeout(Duration::from_millis(timeout));
+        }
+        vm
+    }
+}
@@ -5,39 +5,100 @@ use std::{
 };
 
 use crate::{
-    atom::Atom,
+    atom::{Atom, Span},
+    debug::{DebugInfo, Lambda, Location as SourceLocation, Scope},
     error::Error,
     grammar::ListsParser,
     ir::{
         Arguments, FunctionDefinition, Location, Operator, Statement, Statements,
         TopLevelStatement, Value,
     },
-    opcodes::{Arity, Immediate, OpCode, OpCodes},
-    syscalls,
+    opcodes::{Arity, Constant, Constants, Immediate, OpCode, OpCodes},
+    syscalls::{Registry, Signatures},
 };
 
 //
 // Context.
 //
 
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 pub(crate) struct Context {
     arity: Arity,
+    depths: VecDeque<usize>,
+    file: Option<usize>,
     locals: HashMap<Box<str>, Vec<usize>>,
+    name: Box<str>,
+    origin: Option<(Box<str>, Box<str>)>,
+    scopes: Vec<(usize, Scope)>,
+    span: Option<Span>,
+    spans: VecDeque<Option<Span>>,
     stackn: usize,
     stream: Stream,
 }
 
 impl Context {
-    fn new(arity: Arity) -> Self {
+    fn new(name: Box<str>, arity: Arity, file: Option<usize>, span: Span) -> Self {
         Self {
             arity,
+            depths: VecDeque::default(),
+            file,
             locals: HashMap::default(),
+            name,
+            origin: None,
+            scopes: Vec::default(),
+            span: Some(span),
+            spans: VecDeque::default(),
             stackn: 0,
             stream: Stream::default(),
         }
     }
 
+    fn push(&mut self, op: LabelOrOpCode) {
+        self.push_with_depth(op, self.stackn);
+    }
+
+    fn push_with_depth(&mut self, op: LabelOrOpCode, depth: usize) {
+        self.depths.push_back(depth);
+        self.spans.push_back(self.span);
+        self.stream.push_back(op);
+    }
+
+    fn push_front_with_depth(&mut self, op: LabelOrOpCode, depth: usize) {
+        //
+        // Shift the scopes.
+        //
+        self.scopes.iter_mut().for_each(|(start, _)| *start += 1);
+        //
+        // Prepend the opcode.
+        //
+        self.depths.push_front(depth);
+        self.spans.push_front(self.span);
+        self.stream.push_front(op);
+    }
+
+    fn mark_scope(&mut self) {
+        //
+        // Collect the visible bindings.
+        //
+        let mut scope: Scope = self
+            .locals
+            .iter()
+            .filter_map(|(k, v)| v.last().map(|v| (k.clone(), *v)))
+            .collect();
+        //
+        // Sort them by stack slot.
+        //
+        scope.sort_by_key(|(_, v)| *v);
+        //
+        // Track the scope.
+        //
+        self.scopes.push((self.stream.len(), scope));
+    }
+
+    fn leave_scope(&mut self) {
+        self.scopes.push((self.stream.len(), Scope::default()));
+    }
+
     fn track_arguments(&mut self, args: &Arguments) {
         self.track_arguments_and_closure(args, &BTreeSet::new());
     }
@@ -57,6 +118,10 @@ impl Context {
             self.locals.entry(v.clone()).or_default().push(self.stackn);
             self.stackn += 1;
         });
+        //
+        // Track the scope.
+        //
+        self.mark_scope();
     }
 
     #[cfg(test)]
@@ -69,13 +134,14 @@ impl Context {
 // OpCode or reference.
 //
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq)]
 pub(crate) enum LabelOrOpCode {
     Branch(Box<str>),
     BranchIfNot(Box<str>),
     Funcall(Box<str>),
     Get(Box<str>),
     OpCode(OpCode),
+    Try(Box<str>),
 }
 
 impl From<OpCode> for LabelOrOpCode {
@@ -94,22 +160,48 @@ type Stream = VecDeque<LabelOrOpCode>;
 // Symbols and OpCodes.
 //
 
-pub type SymbolsAndOpCodes = (Vec<(Box<str>, usize, Arity)>, OpCodes);
+pub type SymbolsAndOpCodes = (Vec<(Box<str>, usize, Arity)>, OpCodes, Constants);
 
 //
 // Compiler.
 //
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct Compiler {
     blocks: Vec<(Box<str>, Context)>,
+    constant_ids: HashMap<Constant, u32>,
+    constants: Constants,
+    debug: DebugInfo,
     defuns: HashMap<Box<str>, Arity>,
+    file: Option<usize>,
     labels: HashMap<Box<str>, usize>,
     lcount: usize,
+    modules: Vec<Box<str>>,
+    references: HashMap<Box<str>, (Span, Vec<Box<str>>)>,
+    sources: Vec<(Box<str>, String)>,
+    symbols: Vec<(Box<str>, usize, Arity)>,
+    syscalls: Signatures,
 }
 
 impl Compiler {
-    pub fn compile(mut self, atoms: Vec<Rc<Atom>>) -> Result<SymbolsAndOpCodes, Error> {
+    pub fn with_registry(mut self, registry: &Registry) -> Self {
+        self.syscalls = registry.signatures();
+        self
+    }
+
+    pub fn with_source(mut self, path: &str, source: &str) -> Self {
+        self.file = Some(self.track_source(path, source));
+        self
+    }
+
+    pub fn compile(self, atoms: Vec<Rc<Atom>>) -> Result<SymbolsAndOpCodes, Error> {
+        self.compile_with_debug_info(atoms).map(|(v, _)| v)
+    }
+
+    pub fn compile_with_debug_info(
+        mut self,
+        atoms: Vec<Rc<Atom>>,
+    ) -> Result<(SymbolsAndOpCodes, DebugInfo), Error> {
         //
         // Rewrite the atoms using our intermediate representation.
         //
@@ -126,10 +218,64 @@ impl Compiler {
         //
         let live_defuns = self.collect_live_defuns()?;
         //
-        // Serialize the live streams.
+        // Emit the live streams.
+        //
+        let mut opcodes = Vec::new();
+        self.emit(live_defuns, &mut opcodes)?;
+        //
+        // Done.
+        //
+        Ok(((self.symbols, opcodes, self.constants), self.debug))
+    }
+
+    pub fn extend(&mut self, atoms: Vec<Rc<Atom>>, ops: &mut OpCodes) -> Result<(), Error> {
+        //
+        // Compile the statements in a copy of the compilation unit, so that
+        // it is left untouched on error.
+        //
+        let mut unit = self.clone();
+        let count = ops.len();
+        let result = atoms
+            .into_iter()
+            .map(TopLevelStatement::try_from)
+            .collect::<Result<_, _>>()
+            .and_then(|stmts| unit.load_and_compile(stmts))
+            .and_then(|_| unit.emit(None, ops));
+        //
+        // Commit the compilation unit on success, drop the opcodes otherwise.
+        //
+        match result {
+            Ok(()) => *self = unit,
+            Err(_) => ops.truncate(count),
+        }
+        //
+        // Done.
+        //
+        result
+    }
+
+    pub fn constants(&self) -> &[Constant] {
+        &self.constants
+    }
+
+    pub fn debug_info(&self) -> &DebugInfo {
+        &self.debug
+    }
+
+    pub fn symbols(&self) -> &[(Box<str>, usize, Arity)] {
+        &self.symbols
+    }
+
+    fn emit(
+        &mut self,
+        live_defuns: Option<HashSet<String>>,
+        ops: &mut OpCodes,
+    ) -> Result<(), Error> {
+        //
+        // Serialize the streams past the existing opcodes.
         //
-        let (index, stream) = self
-            .blocks
+        let base = ops.len();
+        let (index, stream, debug) = std::mem::take(&mut self.blocks)
             .into_iter()
             .filter(|(k, _)| {
                 live_defuns
@@ -138,17 +284,48 @@ impl Compiler {
                     .unwrap_or(true)
             })
             .fold(
-                (Vec::new(), Vec::new()),
-                |(mut offsets, mut opcodes), (name, ctxt)| {
-                    offsets.push((name, opcodes.len(), ctxt.arity));
+                (Vec::new(), Vec::new(), DebugInfo::default()),
+                |(mut offsets, mut opcodes, mut debug), (name, ctxt)| {
+                    let start = base + opcodes.len();
+                    //
+                    // Track the origin of lambdas.
+                    //
+                    if let Some((parent, text)) = ctxt.origin {
+                        let name = name.clone();
+                        debug.lambdas.push(Lambda { name, parent, text });
+                    }
+                    //
+                    // Resolve the source locations.
+                    //
+                    let locations = ctxt.spans.into_iter().map(|span| {
+                        let (_, source) = self.sources.get(ctxt.file?)?;
+                        Some(SourceLocation::new(ctxt.file?, source, span?.start))
+                    });
+                    //
+                    // Track the stack depths, locations and scopes.
+                    //
+                    debug.depths.extend(ctxt.depths);
+                    debug.locations.extend(locations);
+                    debug.scopes.push((start, Scope::default()));
+                    debug
+                        .scopes
+                        .extend(ctxt.scopes.into_iter().map(|(k, v)| (start + k, v)));
+                    //
+                    // Track the symbol and its opcodes.
+                    //
+                    offsets.push((name, start, ctxt.arity));
                     opcodes.extend(ctxt.stream);
-                    (offsets, opcodes)
+                    (offsets, opcodes, debug)
                 },
             );
         //
+        // Track the new symbols.
+        //
+        self.symbols.extend(index);
+        //
         // Convert the stream to opcodes.
         //
-        let opcodes = stream
+        let opcodes: OpCodes = stream
             .into_iter()
             .map(|v| match v {
                 LabelOrOpCode::Branch(v) => {
@@ -163,10 +340,9 @@ impl Compiler {
                     //
                     // Get the address of the symbol.
                     //
-                    let (addr, argcnt) = index
-                        .iter()
-                        .find_map(|(k, a, n)| (k == &sym).then_some((*a, *n)))
-                        .ok_or(Error::InvalidSymbol(sym))?;
+                    let Some((addr, argcnt)) = self.address(&sym) else {
+                        return Err(self.locate(Error::InvalidSymbol(sym.clone()), &sym));
+                    };
                     //
                     // Push the funcall.
                     //
@@ -176,28 +352,62 @@ impl Compiler {
                     //
                     // Get the address of the symbol.
                     //
-                    let (addr, argcnt) = index
-                        .iter()
-                        .find_map(|(k, a, n)| (k == &sym).then_some((*a, *n)))
-                        .ok_or(Error::UnresolvedSymbol(sym))?;
+                    let Some((addr, argcnt)) = self.address(&sym) else {
+                        return Err(self.locate(Error::UnresolvedSymbol(sym.clone()), &sym));
+                    };
                     //
                     // Push the funcall.
                     //
                     Ok(OpCode::Psh(Immediate::funcall(addr, argcnt)))
                 }
                 LabelOrOpCode::OpCode(v) => Ok(v),
+                LabelOrOpCode::Try(v) => {
+                    let delta = self.labels.get(&v).copied().ok_or(Error::InvalidLabel(v))?;
+                    Ok(OpCode::Try(delta as isize))
+                }
             })
             .collect::<Result<_, Error>>()?;
         //
+        // Append the opcodes and their debug information.
+        //
+        ops.extend(opcodes);
+        self.debug.extend(DebugInfo {
+            files: self.sources.iter().map(|(k, _)| k.clone()).collect(),
+            ..debug
+        });
+        //
         // Done.
         //
-        Ok((index, opcodes))
+        Ok(())
+    }
+
+    fn address(&self, symbol: &str) -> Option<(usize, Arity)> {
+        self.symbols
+            .iter()
+            .find_map(|(k, a, n)| (k.as_ref() == symbol).then_some((*a, *n)))
     }
 
     fn load_and_compile(&mut self, stmts: Vec<TopLevelStatement>) -> Result<(), Error> {
         stmts.iter().try_for_each(|v| match v {
             TopLevelStatement::FunctionDefinition(v) => self.compile_defun(v),
-            TopLevelStatement::Load(v) => self.load_modules(v),
+            TopLevelStatement::Load(v, span) => self.load_modules(v).map_err(|e| e.at(*span)),
+        })
+    }
+
+    fn track_source(&mut self, path: &str, source: &str) -> usize {
+        //
+        // Reuse the existing entry if the source has not changed.
+        //
+        let entry = self
+            .sources
+            .iter()
+            .position(|(k, v)| k.as_ref() == path && v == source);
+        //
+        // Track the source otherwise.
+        //
+        entry.unwrap_or_else(|| {
+            self.sources.push((path.into(), source.to_owned()));
+            self.sources.len() - 1
         })
     }
 
@@ -206,6 +416,21 @@ impl Compiler {
         self.lcount += 1;
         label
     }
+
+    fn locate(&self, error: Error, symbol: &str) -> Error {
+        //
+        // Get the last reference to the symbol.
+        //
+        let Some((span, modules)) = self.references.get(symbol) else {
+            return error;
+        };
+        //
+        // Wrap the located error into its chain of modules.
+        //
+        modules.iter().rev().fold(error.at(*span), |acc, v| {
+            Error::Module(v.clone(), acc.into())
+        })
+    }
 }
 
 //
@@ -244,7 +469,7 @@ impl Compiler {
             // Grab the block.
             //
             let Some((_, ctxt)) = self.blocks.iter().find(|(k, _)| k == &v) else {
-                return Err(Error::UnresolvedSymbol(v));
+                return Err(self.locate(Error::UnresolvedSymbol(v.clone()), &v));
             };
             //
             // Process the block.
@@ -314,7 +539,7 @@ impl Compiler {
         })
     }
 
-    fn load_module(&mut self, name: &str, _items: Option<&[&str]>) -> Result<(), Error> {
+    fn load_module(&mut self, name: &str, items: Option<&[&str]>) -> Result<(), Error> {
         //
         // Compute the source path.
         //
@@ -324,15 +549,29 @@ impl Compiler {
         // Open the source file.
         //
         let mut source = String::new();
-        let mut file = std::fs::File::open(path)?;
+        let mut file = std::fs::File::open(&path)?;
         file.read_to_string(&mut source)?;
+        //
+        // Compile the module, tracking it for the diagnostics.
+        //
+        self.modules.push(path.clone().into_boxed_str());
+        let index = self.track_source(&path, &source);
+        let file = self.file.replace(index);
+        let result = self.compile_module(&source, items);
+        self.file = file;
+        self.modules.pop();
+        //
+        // Done.
+        //
+        result.map_err(|e| Error::Module(path.into_boxed_str(), e.into()))
+    }
+
+    fn compile_module(&mut self, source: &str, items: Option<&[&str]>) -> Result<(), Error> {
         //
         // Parse the source file.
         //
         let parser = ListsParser::new();
-        let atoms = parser
-            .parse(&source)
-            .map_err(|v| Error::Parse(v.to_string()))?;
+        let atoms = parser.parse(source)?;
         //
         // Rewrite the atoms using our intermediate representation.
         //
@@ -343,10 +582,10 @@ impl Compiler {
         //
         // Filter function declarations.
         //
-        if let Some(items) = _items {
+        if let Some(items) = items {
             stmts.retain(|v| match v {
                 TopLevelStatement::FunctionDefinition(v) => items.contains(&v.name().as_ref()),
-                TopLevelStatement::Load(_) => true,
+                TopLevelStatement::Load(..) => true,
             });
         }
         //
@@ -364,13 +603,14 @@ impl Compiler {
     fn compile_defun(&mut self, defun: &FunctionDefinition) -> Result<(), Error> {
         let arity = defun.arguments().arity();
         let argcnt = defun.arguments().len();
-        let mut ctxt = Context::new(arity);
+        let name = defun.name().clone();
+        let mut ctxt = Context::new(name, arity, self.file, defun.span());
         //
         // Make sure the function does not exist.
         //
         if self.defuns.contains_key(defun.name()) {
             let name = defun.name().to_string().into_boxed_str();
-            return Err(Error::FunctionAlreadyDefined(name));
+            return Err(Error::FunctionAlreadyDefined(name).at(defun.span()));
         }
         //
         // Track the function arguments.
@@ -384,7 +624,7 @@ impl Compiler {
         // Rotate the arguments and the return address.
         //
         if argcnt > 0 {
-            ctxt.stream.push_back(OpCode::Rot(argcnt + 1).into());
+            ctxt.push_with_depth(OpCode::Rot(argcnt + 1).into(), argcnt + 1);
         }
         //
         // Compile the statements.
@@ -394,17 +634,21 @@ impl Compiler {
         // Generate the postamble if necessary.
         //
         if !defun.statements().is_tail_call() {
+            //
+            // The arguments are no longer addressable.
+            //
+            ctxt.leave_scope();
             //
             // Pop the arguments.
             //
             if argcnt > 0 {
-                ctxt.stream.push_back(OpCode::Rot(argcnt + 1).into());
-                ctxt.stream.push_back(OpCode::Pop(argcnt).into());
+                ctxt.push(OpCode::Rot(argcnt + 1).into());
+                ctxt.push(OpCode::Pop(argcnt).into());
             }
             //
             // Inject the return call.
             //
-            ctxt.stream.push_back(OpCode::Ret.into());
+            ctxt.push(OpCode::Ret.into());
             ctxt.stackn -= 1;
         }
         //
@@ -441,6 +685,7 @@ impl Compiler {
                 .entry(symbol.clone())
                 .or_default()
                 .push(ctxt.stackn - 1);
+            ctxt.mark_scope();
             //
             // Done.
             //
@@ -469,6 +714,7 @@ impl Compiler {
                     ctxt.locals.remove(symbol);
                 }
             }
+            ctxt.mark_scope();
             //
             // Done.
             //
@@ -482,7 +728,7 @@ impl Compiler {
             // Drop the previous result.
             //
             if indx > 0 {
-                ctxt.stream.push_back(OpCode::Pop(1).into());
+                ctxt.push(OpCode::Pop(1).into());
                 ctxt.stackn -= 1;
             }
             //
@@ -496,9 +742,26 @@ impl Compiler {
         &mut self,
         ctxt: &mut Context,
         stmt: &Statement,
+    ) -> Result<(), Error> {
+        //
+        // Attribute the opcodes of the statement to its location, if any.
+        //
+        let Some(span) = stmt.span() else {
+            return self.compile_statement_body(ctxt, stmt);
+        };
+        let outer = ctxt.span.replace(span);
+        let result = self.compile_statement_body(ctxt, stmt);
+        ctxt.span = outer;
+        result
+    }
+
+    fn compile_statement_body(
+        &mut self,
+        ctxt: &mut Context,
+        stmt: &Statement,
     ) -> Result<(), Error> {
         match stmt {
-            Statement::Apply(op, args, Location::Any) => {
+            Statement::Apply(op, args, Location::Any, _) => {
                 //
                 // Compile the arguments.
                 //
@@ -512,7 +775,7 @@ impl Compiler {
                 //
                 match op.as_ref() {
                     Statement::Operator(_) => (),
-                    _ => ctxt.stream.push_back(OpCode::Call(args.len()).into()),
+                    _ => ctxt.push(OpCode::Call(args.len()).into()),
                 }
                 //
                 // Update the stack.
@@ -523,11 +786,11 @@ impl Compiler {
                 //
                 Ok(())
             }
-            Statement::Apply(op, args, Location::Tail) => {
+            Statement::Apply(op, args, Location::Tail, _) => {
                 //
                 // Get the symbol of the tail call.
                 //
-                let Statement::Symbol(symbol) = op.as_ref() else {
+                let Statement::Symbol(symbol, _) = op.as_ref() else {
                     todo!();
                 };
                 //
@@ -546,12 +809,12 @@ impl Compiler {
                         /*
                          * Pack the arguments into a list.
                          */
-                        ctxt.stream.push_back(OpCode::Lst(args.len()).into());
+                        ctxt.push(OpCode::Lst(args.len()).into());
                         /*
                          * Pop the argument.
                          */
-                        ctxt.stream.push_back(OpCode::Rot(2).into());
-                        ctxt.stream.push_back(OpCode::Pop(1).into());
+                        ctxt.push(OpCode::Rot(2).into());
+                        ctxt.push(OpCode::Pop(1).into());
                         /*
                          * Update the stack.
                          */
@@ -567,11 +830,11 @@ impl Compiler {
                         /*
                          * Rotate the stack to push the original arguments ahead.
                          */
-                        ctxt.stream.push_back(OpCode::Rot(2 * cnt).into());
+                        ctxt.push(OpCode::Rot(2 * cnt).into());
                         /*
                          * Pop the old arguments.
                          */
-                        ctxt.stream.push_back(OpCode::Pop(cnt).into());
+                        ctxt.push(OpCode::Pop(cnt).into());
                         /*
                          * Return the argument count.
                          */
@@ -582,11 +845,11 @@ impl Compiler {
                         /*
                          * Rotate the stack to push the original arguments ahead.
                          */
-                        ctxt.stream.push_back(OpCode::Rtm(2 * cnt, cnt).into());
+                        ctxt.push(OpCode::Rtm(2 * cnt, cnt).into());
                         /*
                          * Pop the old arguments.
                          */
-                        ctxt.stream.push_back(OpCode::Pop(cnt).into());
+                        ctxt.push(OpCode::Pop(cnt).into());
                         /*
                          * Return the argument count.
                          */
@@ -597,19 +860,19 @@ impl Compiler {
                         /*
                          * Push an empty list.
                          */
-                        ctxt.stream.push_back(OpCode::Psh(Immediate::Nil).into());
+                        ctxt.push(OpCode::Psh(Immediate::Nil).into());
                         /*
                          * Restore the new arguments order.
                          */
-                        ctxt.stream.push_back(OpCode::Rot(cnt).into());
+                        ctxt.push(OpCode::Rot(cnt).into());
                         /*
                          * Rotate the stack to push the original arguments ahead.
                          */
-                        ctxt.stream.push_back(OpCode::Rot(2 * cnt).into());
+                        ctxt.push(OpCode::Rot(2 * cnt).into());
                         /*
                          * Pop the old arguments.
                          */
-                        ctxt.stream.push_back(OpCode::Pop(cnt).into());
+                        ctxt.push(OpCode::Pop(cnt).into());
                         /*
                          * Return the argument count.
                          */
@@ -621,23 +884,23 @@ impl Compiler {
                         /*
                          * Prepare the new arguments.
                          */
-                        ctxt.stream.push_back(OpCode::Rtm(args.len(), rem).into());
+                        ctxt.push(OpCode::Rtm(args.len(), rem).into());
                         /*
                          * Pack the remainder arguments into a list.
                          */
-                        ctxt.stream.push_back(OpCode::Lst(rem).into());
+                        ctxt.push(OpCode::Lst(rem).into());
                         /*
                          * Restore the new arguments order.
                          */
-                        ctxt.stream.push_back(OpCode::Rot(cnt).into());
+                        ctxt.push(OpCode::Rot(cnt).into());
                         /*
                          * Rotate the stack to push the original arguments ahead.
                          */
-                        ctxt.stream.push_back(OpCode::Rtm(2 * cnt, cnt).into());
+                        ctxt.push(OpCode::Rtm(2 * cnt, cnt).into());
                         /*
                          * Pop the old arguments.
                          */
-                        ctxt.stream.push_back(OpCode::Pop(cnt).into());
+                        ctxt.push(OpCode::Pop(cnt).into());
                         /*
                          * Return the argument count.
                          */
@@ -652,18 +915,19 @@ impl Compiler {
                 //
                 // Generate the branch.
                 //
-                ctxt.stream.push_back(OpCode::Br(-(offset as isize)).into());
+                ctxt.push(OpCode::Br(-(offset as isize)).into());
                 //
                 // Done.
                 //
                 Ok(())
             }
-            Statement::Lambda(args, statements) => {
-                let mut next = Context::new(args.arity());
+            Statement::Lambda(args, statements, span) => {
                 //
                 // Generate a name for the lambda.
                 //
                 let name = self.label("LAMBDA");
+                let mut next = Context::new(name.clone(), args.arity(), ctxt.file, *span);
+                next.origin = Some((ctxt.name.clone(), stmt.to_string().into_boxed_str()));
                 //
                 // Grab the closure.
                 //
@@ -693,19 +957,20 @@ impl Compiler {
                     //
                     // Generate the preamble.
                     //
-                    next.stream.push_front(OpCode::Rot(argcnt + 1).into());
+                    next.push_front_with_depth(OpCode::Rot(argcnt + 1).into(), argcnt + 1);
                     //
                     // Generate the postamble.
                     //
                     if argcnt > 0 {
-                        next.stream.push_back(OpCode::Rot(argcnt + 1).into());
-                        next.stream.push_back(OpCode::Pop(argcnt).into());
+                        next.leave_scope();
+                        next.push(OpCode::Rot(argcnt + 1).into());
+                        next.push(OpCode::Pop(argcnt).into());
                     }
                 }
                 //
                 // Inject the return call.
                 //
-                next.stream.push_back(OpCode::Ret.into());
+                next.push(OpCode::Ret.into());
                 next.stackn -= 1;
                 //
                 // Grab the closure symbols.
@@ -715,13 +980,13 @@ impl Compiler {
                     // Get the symbol index.
                     //
                     let Some(index) = ctxt.locals.get(v).and_then(|v| v.last()) else {
-                        return Err(Error::InvalidSymbol(v.clone()));
+                        return Err(self.locate(Error::InvalidSymbol(v.clone()), v));
                     };
                     //
                     // Inject the push.
                     //
                     let opcode = OpCode::Get(ctxt.stackn - *index).into();
-                    ctxt.stream.push_back(opcode);
+                    ctxt.push(opcode);
                     ctxt.stackn += 1;
                     //
                     // Done.
@@ -731,12 +996,12 @@ impl Compiler {
                 //
                 // Inject the funcall.
                 //
-                ctxt.stream.push_back(LabelOrOpCode::Funcall(name.clone()));
+                ctxt.push(LabelOrOpCode::Funcall(name.clone()));
                 ctxt.stackn += 1;
                 //
                 // Pack the closure.
                 //
-                ctxt.stream.push_back(OpCode::Pak(closure.len() + 1).into());
+                ctxt.push(OpCode::Pak(closure.len() + 1).into());
                 ctxt.stackn -= closure.len();
                 //
                 // Save the block.
@@ -756,12 +1021,24 @@ impl Compiler {
                     // Arithmetics.
                     //
                     Operator::Add => OpCode::Add.into(),
+                    Operator::Div => OpCode::Div.into(),
                     Operator::Ge => OpCode::Ge.into(),
                     Operator::Gt => OpCode::Gt.into(),
                     Operator::Le => OpCode::Le.into(),
                     Operator::Lt => OpCode::Lt.into(),
+                    Operator::Mod => OpCode::Mod.into(),
+                    Operator::Mul => OpCode::Mul.into(),
+                    Operator::Neg => OpCode::Neg.into(),
                     Operator::Sub => OpCode::Sub.into(),
                     //
+                    // Bitwise operations.
+                    //
+                    Operator::Band => OpCode::Band.into(),
+                    Operator::Bor => OpCode::Bor.into(),
+                    Operator::Bxor => OpCode::Bxor.into(),
+                    Operator::Shl => OpCode::Shl.into(),
+                    Operator::Shr => OpCode::Shr.into(),
+                    //
                     // Logic.
                     //
                     Operator::And => OpCode::And.into(),
@@ -779,10 +1056,16 @@ impl Compiler {
                     // String.
                     //
                     Operator::Str => OpCode::Str.into(),
+                    Operator::Len => OpCode::Len.into(),
+                    Operator::Cat => OpCode::Cat.into(),
+                    Operator::Substr => OpCode::Substr.into(),
+                    Operator::Cmp => OpCode::Cmp.into(),
+                    Operator::Find => OpCode::Find.into(),
                     //
                     // Predicates.
                     //
                     Operator::IsChr => OpCode::IsChr.into(),
+                    Operator::IsFlt => OpCode::IsFlt.into(),
                     Operator::IsNum => OpCode::IsNum.into(),
                     Operator::IsLst => OpCode::IsLst.into(),
                     Operator::IsNil => OpCode::IsNil.into(),
@@ -792,7 +1075,7 @@ impl Compiler {
                 //
                 // Push the opcode.
                 //
-                ctxt.stream.push_back(opcode);
+                ctxt.push(opcode);
                 //
                 // Update the stack.
                 //
@@ -802,16 +1085,16 @@ impl Compiler {
                 //
                 Ok(())
             }
-            Statement::SysCall(sym) => {
+            Statement::SysCall(sym, span) => {
                 //
                 // Get the syscall parameters.
                 //
-                let (index, argcnt) = syscalls::get(sym)?;
+                let (index, argcnt) = self.syscalls.get(sym).map_err(|e| e.at(*span))?;
                 //
                 // Push the opcode.
                 //
                 let syscall = Immediate::Syscall(index, argcnt);
-                ctxt.stream.push_back(OpCode::Psh(syscall).into());
+                ctxt.push(OpCode::Psh(syscall).into());
                 //
                 // Update the stack.
                 //
@@ -821,6 +1104,60 @@ impl Compiler {
                 //
                 Ok(())
             }
+            Statement::Catch(handler, stmts) => {
+                //
+                // Compile the handler.
+                //
+                self.compile_statement(ctxt, handler)?;
+                //
+                // Install the handler frame.
+                //
+                let name = self.label("BEGIN_CATCH");
+                let start = ctxt.stream.len();
+                let label = LabelOrOpCode::Try(name.clone());
+                ctxt.push(label);
+                //
+                // Compile the statements.
+                //
+                if stmts.is_empty() {
+                    self.compile_statement(ctxt, &Statement::Value(Value::Nil))?;
+                } else {
+                    self.compile_statements(ctxt, stmts)?;
+                }
+                //
+                // Remove the handler frame and drop the handler.
+                //
+                ctxt.push(OpCode::EndTry.into());
+                ctxt.push(OpCode::Rot(2).into());
+                ctxt.push(OpCode::Pop(1).into());
+                ctxt.stackn -= 1;
+                //
+                // Generate the branch past the handler call.
+                //
+                let end = self.label("END_CATCH");
+                let end_start = ctxt.stream.len();
+                let label = LabelOrOpCode::Branch(end.clone());
+                ctxt.push(label);
+                //
+                // Track the handler label.
+                //
+                let delta = ctxt.stream.len() - start;
+                self.labels.insert(name, delta);
+                //
+                // Call the handler with the thrown value.
+                //
+                ctxt.push(OpCode::Swp.into());
+                ctxt.push(OpCode::Call(1).into());
+                //
+                // Track the end label.
+                //
+                let delta = ctxt.stream.len() - end_start;
+                self.labels.insert(end, delta);
+                //
+                // Done.
+                //
+                Ok(())
+            }
             Statement::IfThenElse(cond, then, else_) => {
                 //
                 // Compile the condition.
@@ -833,7 +1170,7 @@ impl Compiler {
                 let name = self.label("BEGIN_ELSE");
                 let start = ctxt.stream.len();
                 let label = LabelOrOpCode::BranchIfNot(name.clone());
-                ctxt.stream.push_back(label);
+                ctxt.push(label);
                 //
                 // Compile THEN.
                 //
@@ -857,7 +1194,7 @@ impl Compiler {
                 //
                 if !then.is_tail_call() {
                     let label = LabelOrOpCode::Branch(name.clone());
-                    ctxt.stream.push_back(label);
+                    ctxt.push(label);
                 }
                 //
                 // Compile ELSE.
@@ -887,28 +1224,68 @@ impl Compiler {
                 //
                 self.compile_statements(ctxt, statements)?;
                 //
+                // Clear the bindings from the locals.
+                //
+                self.clear_bindings(ctxt, bindings)?;
+                //
                 // Pop the bindings.
                 //
                 if argcnt > 0 {
-                    ctxt.stream.push_back(OpCode::Rot(argcnt + 1).into());
-                    ctxt.stream.push_back(OpCode::Pop(argcnt).into());
+                    ctxt.push(OpCode::Rot(argcnt + 1).into());
+                    ctxt.push(OpCode::Pop(argcnt).into());
+                    ctxt.stackn -= argcnt;
                 }
                 //
-                // Clear the bindings from the locals.
+                // Done.
                 //
-                self.clear_bindings(ctxt, bindings)?;
+                Ok(())
+            }
+            Statement::Prog(stmts) => self.compile_statements(ctxt, stmts),
+            Statement::Throw(stmt) => {
+                //
+                // Compile the value.
+                //
+                self.compile_statement(ctxt, stmt)?;
+                //
+                // Throw the value. It stays accounted for as the result of the
+                // statement, even though the throw never falls through.
+                //
+                ctxt.push(OpCode::Throw.into());
                 //
                 // Done.
                 //
                 Ok(())
             }
-            Statement::Prog(stmts) => self.compile_statements(ctxt, stmts),
-            Statement::Symbol(symbol) => self.compile_symbol(ctxt, symbol),
-            Statement::Value(value) => Self::compile_value(ctxt, value),
+            Statement::Yield(stmt) => {
+                //
+                // Compile the value.
+                //
+                self.compile_statement(ctxt, stmt)?;
+                //
+                // Yield the value, which is also the result of the statement.
+                //
+                ctxt.push(OpCode::Yield.into());
+                //
+                // Done.
+                //
+                Ok(())
+            }
+            Statement::Symbol(symbol, span) => self.compile_symbol(ctxt, symbol, *span),
+            Statement::Value(value) => self.compile_value(ctxt, value),
         }
     }
 
-    fn compile_symbol(&mut self, ctxt: &mut Context, symbol: &Box<str>) -> Result<(), Error> {
+    fn compile_symbol(
+        &mut self,
+        ctxt: &mut Context,
+        symbol: &Box<str>,
+        span: Span,
+    ) -> Result<(), Error> {
+        //
+        // Track the reference for the diagnostics.
+        //
+        let reference = (span, self.modules.clone());
+        self.references.insert(symbol.clone(), reference);
         //
         // Get the opcode.
         //
@@ -919,7 +1296,7 @@ impl Compiler {
         //
         // Push the opcode.
         //
-        ctxt.stream.push_back(opcode);
+        ctxt.push(opcode);
         //
         // Update the stack tracker.
         //
@@ -930,50 +1307,21 @@ impl Compiler {
         Ok(())
     }
 
-    fn compile_value(ctxt: &mut Context, value: &Value) -> Result<(), Error> {
+    fn compile_value(&mut self, ctxt: &mut Context, value: &Value) -> Result<(), Error> {
         //
-        // Get the opcode.
+        // Get the opcode. Bignums, lists, strings and symbols are pre-built in
+        // the constant pool.
         //
         let opcode = match value {
-            //
-            // Idempotent values.
-            //
-            Value::Nil => OpCode::Psh(Immediate::Nil).into(),
-            Value::True => OpCode::Psh(Immediate::True).into(),
-            Value::Char(v) => OpCode::Psh(Immediate::Char(*v)).into(),
-            Value::Number(v) => OpCode::Psh(Immediate::Number(*v)).into(),
-            //
-            // Only generated as a result of (quote).
-            //
-            Value::Symbol(v) => {
-                let mut symbol = [0_u8; 15];
-                symbol[..v.len()].copy_from_slice(v.as_bytes());
-                OpCode::Psh(Immediate::Symbol(symbol)).into()
-            }
-            Value::Pair(car, cdr) => {
-                //
-                // Process car and cdr.
-                //
-                Self::compile_value(ctxt, cdr)?;
-                Self::compile_value(ctxt, car)?;
-                //
-                // Push cons, consume 2 and producing 1.
-                //
-                let opcode = OpCode::Cons.into();
-                //
-                // Update the stack.
-                //
-                ctxt.stackn -= 2;
-                //
-                // Done.
-                //
-                opcode
+            Value::BigNum(_) | Value::Pair(..) | Value::String(_) | Value::Symbol(_) => {
+                OpCode::Ldc(self.intern(value) as usize)
             }
+            _ => OpCode::Psh(Self::immediate(value)),
         };
         //
         // Push the opcode.
         //
-        ctxt.stream.push_back(opcode);
+        ctxt.push(opcode.into());
         //
         // Update the stack tracker.
         //
@@ -983,6 +1331,51 @@ impl Compiler {
         //
         Ok(())
     }
+
+    fn intern(&mut self, value: &Value) -> u32 {
+        //
+        // Build the cell.
+        //
+        let constant = match value {
+            Value::Pair(car, cdr) => {
+                //
+                // Intern car and cdr first, cells only reference earlier ones.
+                //
+                let car = self.intern(car);
+                let cdr = self.intern(cdr);
+                Constant::Pair(car, cdr)
+            }
+            Value::BigNum(v) => Constant::BigNum(v.to_signed_bytes_le().into()),
+            Value::String(v) => Constant::String(v.as_bytes().into()),
+            Value::Symbol(v) => Constant::Symbol(v.clone()),
+            _ => Constant::Immediate(Self::immediate(value)),
+        };
+        //
+        // Append the cell to the pool if it is not already there.
+        //
+        let index = self.constants.len() as u32;
+        let index = *self.constant_ids.entry(constant.clone()).or_insert(index);
+        if index as usize == self.constants.len() {
+            self.constants.push(constant);
+        }
+        index
+    }
+
+    fn immediate(value: &Value) -> Immediate {
+        match value {
+            //
+            // Idempotent values.
+            //
+            Value::Nil => Immediate::Nil,
+            Value::True => Immediate::True,
+            Value::Char(v) => Immediate::Char(*v),
+            Value::Number(v) => Immediate::Number(*v),
+            Value::Float(v) => Immediate::Float(*v),
+            Value::BigNum(_) | Value::Pair(..) | Value::String(_) | Value::Symbol(_) => {
+                unreachable!("Bignums, pairs, strings and symbols are not immediate values")
+            }
+        }
+    }
 }
 
 //
@@ -1000,11 +1393,23 @@ impl Compiler {
             //
             Self::lift(Operator::Add),
             Self::lift(Operator::Sub),
+            Self::lift(Operator::Mul),
+            Self::lift(Operator::Div),
+            Self::lift(Operator::Mod),
+            Self::lift(Operator::Neg),
             Self::lift(Operator::Ge),
             Self::lift(Operator::Gt),
             Self::lift(Operator::Le),
             Self::lift(Operator::Lt),
             //
+            // Bitwise operations.
+            //
+            Self::lift(Operator::Band),
+            Self::lift(Operator::Bor),
+            Self::lift(Operator::Bxor),
+            Self::lift(Operator::Shl),
+            Self::lift(Operator::Shr),
+            //
             // Logic.
             //
             Self::lift(Operator::And),
@@ -1019,9 +1424,19 @@ impl Compiler {
             Self::lift(Operator::Cdr),
             Self::lift(Operator::Cons),
             //
+            // String operations.
+            //
+            Self::lift(Operator::Str),
+            Self::lift(Operator::Len),
+            Self::lift(Operator::Cat),
+            Self::lift(Operator::Substr),
+            Self::lift(Operator::Cmp),
+            Self::lift(Operator::Find),
+            //
             // Predicates.
             //
             Self::lift(Operator::IsChr),
+            Self::lift(Operator::IsFlt),
             Self::lift(Operator::IsNum),
             Self::lift(Operator::IsLst),
             Self::lift(Operator::IsNil),
@@ -1029,9 +1444,12 @@ impl Compiler {
             Self::lift(Operator::IsTru),
         ];
         //
-        // Compile the statements.
+        // Compile the statements, which have no source location.
         //
-        self.load_and_compile(stmts)
+        let file = self.file.take();
+        let result = self.load_and_compile(stmts);
+        self.file = file;
+        result
     }
 
     fn lift(op: Operator) -> TopLevelStatement {
@@ -1047,8 +1465,13 @@ impl Compiler {
         //
         let apply = Statement::Apply(
             Statement::Operator(op).into(),
-            Statements::new(args.clone().into_iter().map(Statement::Symbol).collect()),
+            Statements::new(
+                args.iter()
+                    .map(|v| Statement::Symbol(v.clone(), Span::default()))
+                    .collect(),
+            ),
             Location::Any,
+            Span::default(),
         );
         //
         // Build the statements.
@@ -1061,6 +1484,7 @@ impl Compiler {
             op.to_string().into_boxed_str(),
             Arguments::List(args),
             stmts,
+            Span::default(),
         );
         //
         // Done.
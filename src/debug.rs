@@ -0,0 +1,109 @@
+use bincode::{Decode, Encode};
+
+//
+// Scope.
+//
+
+pub type Scope = Vec<(Box<str>, usize)>;
+
+//
+// Source location.
+//
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct Location {
+    pub file: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Location {
+    pub(crate) fn new(file: usize, source: &str, offset: usize) -> Self {
+        //
+        // Clamp the offset to the source.
+        //
+        let offset = offset.min(source.len());
+        let start = source[..offset].rfind('\n').map(|v| v + 1).unwrap_or(0);
+        //
+        // Compute the line and column numbers.
+        //
+        let line = source[..offset].matches('\n').count() + 1;
+        let column = source[start..offset].chars().count() + 1;
+        //
+        // Done.
+        //
+        Self { file, line, column }
+    }
+}
+
+//
+// Lambda origin.
+//
+
+#[derive(Clone, Debug, PartialEq, Encode, Decode)]
+pub struct Lambda {
+    pub name: Box<str>,
+    pub parent: Box<str>,
+    pub text: Box<str>,
+}
+
+//
+// Debug information.
+//
+
+#[derive(Clone, Debug, Default, PartialEq, Encode, Decode)]
+pub struct DebugInfo {
+    pub(crate) depths: Vec<usize>,
+    pub(crate) files: Vec<Box<str>>,
+    pub(crate) lambdas: Vec<Lambda>,
+    pub(crate) locations: Vec<Option<Location>>,
+    pub(crate) scopes: Vec<(usize, Scope)>,
+}
+
+impl DebugInfo {
+    pub fn depth(&self, pc: usize) -> Option<usize> {
+        self.depths.get(pc).copied()
+    }
+
+    pub fn file(&self, index: usize) -> Option<&str> {
+        self.files.get(index).map(AsRef::as_ref)
+    }
+
+    pub fn lambda(&self, name: &str) -> Option<&Lambda> {
+        self.lambdas.iter().find(|v| v.name.as_ref() == name)
+    }
+
+    pub fn has_locations(&self) -> bool {
+        self.locations.iter().any(Option::is_some)
+    }
+
+    pub fn location(&self, pc: usize) -> Option<Location> {
+        self.locations.get(pc).copied().flatten()
+    }
+
+    pub fn locals(&self, pc: usize) -> &[(Box<str>, usize)] {
+        //
+        // Find the last scope that starts at or before PC.
+        //
+        let index = self.scopes.partition_point(|(start, _)| *start <= pc);
+        //
+        // Return its bindings.
+        //
+        match index {
+            0 => &[],
+            n => &self.scopes[n - 1].1,
+        }
+    }
+
+    pub fn scopes(&self) -> &[(usize, Scope)] {
+        &self.scopes
+    }
+
+    pub(crate) fn extend(&mut self, other: DebugInfo) {
+        self.depths.extend(other.depths);
+        self.files = other.files;
+        self.lambdas.extend(other.lambdas);
+        self.locations.extend(other.locations);
+        self.scopes.extend(other.scopes);
+    }
+}
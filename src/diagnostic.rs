@@ -0,0 +1,121 @@
+use std::fmt::Display;
+
+use crate::{atom::Span, error::Error};
+
+//
+// Diagnostic.
+//
+
+pub struct Diagnostic<'a> {
+    error: &'a Error,
+    file: &'a str,
+    source: &'a str,
+}
+
+impl<'a> Diagnostic<'a> {
+    pub fn new(error: &'a Error, file: &'a str, source: &'a str) -> Self {
+        Self {
+            error,
+            file,
+            source,
+        }
+    }
+}
+
+impl Display for Diagnostic<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        //
+        // Unwrap the chain of modules and the location of the error.
+        //
+        let mut files = vec![self.file];
+        let mut error = self.error;
+        let mut span = None;
+        loop {
+            match error {
+                Error::Located(v, inner) => {
+                    span = Some(*v);
+                    error = inner;
+                }
+                Error::Module(path, inner) => {
+                    files.push(path);
+                    error = inner;
+                }
+                _ => break,
+            }
+        }
+        //
+        // Print the error message.
+        //
+        writeln!(f, "error: {error}")?;
+        //
+        // Print the location of the error.
+        //
+        let file = files.last().copied().unwrap_or(self.file);
+        match span {
+            Some(span) if files.len() == 1 => Self::snippet(f, file, self.source, span)?,
+            Some(span) => match std::fs::read_to_string(file) {
+                Ok(source) => Self::snippet(f, file, &source, span)?,
+                Err(_) => writeln!(f, " --> {file}")?,
+            },
+            None => writeln!(f, " --> {file}")?,
+        }
+        //
+        // Print the chain of modules.
+        //
+        files
+            .windows(2)
+            .rev()
+            .try_for_each(|v| writeln!(f, "  = note: {} loaded from {}", v[1], v[0]))
+    }
+}
+
+impl Diagnostic<'_> {
+    fn snippet(
+        f: &mut std::fmt::Formatter<'_>,
+        file: &str,
+        source: &str,
+        span: Span,
+    ) -> std::fmt::Result {
+        //
+        // Clamp the span to the source.
+        //
+        let start = span.start.min(source.len());
+        let end = span.end.clamp(start, source.len());
+        //
+        // Find the line of the span.
+        //
+        let offset = source[..start].rfind('\n').map(|v| v + 1).unwrap_or(0);
+        let length = source[offset..].find('\n').unwrap_or(source.len() - offset);
+        let text = source[offset..offset + length].replace('\t', "    ");
+        //
+        // Compute the line and column numbers.
+        //
+        let line = source[..start].matches('\n').count() + 1;
+        let column = source[offset..start].chars().count() + 1;
+        //
+        // Compute the underline, limited to the first line of the span.
+        //
+        let indent: usize = source[offset..start].chars().map(Self::width).sum();
+        let width: usize = source[start..end.min(offset + length)]
+            .chars()
+            .map(Self::width)
+            .sum();
+        let gutter = " ".repeat(line.to_string().len());
+        //
+        // Print the snippet.
+        //
+        writeln!(f, "{gutter}--> {file}:{line}:{column}")?;
+        writeln!(f, "{gutter} |")?;
+        writeln!(f, "{line} | {text}")?;
+        writeln!(
+            f,
+            "{gutter} | {}{}",
+            " ".repeat(indent),
+            "^".repeat(width.max(1))
+        )
+    }
+
+    fn width(c: char) -> usize {
+        if c == '\t' { 4 } else { 1 }
+    }
+}
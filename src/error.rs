@@ -1,11 +1,28 @@
 use std::env::VarError;
 
+use lalrpop_util::ParseError;
 use thiserror::Error;
 
+use crate::{atom::Span, binary::FORMAT_VERSION, opcodes::OpCode, stack::Kind};
+
 #[derive(Debug, Error)]
 pub enum Error {
+    #[error("Arithmetic overflow at {0:04}")]
+    ArithmeticOverflow(usize),
+    #[error("Corrupted binary: checksum mismatch")]
+    CorruptedBinary,
+    #[error("Deadline exceeded at {0:04}")]
+    DeadlineExceeded(usize),
+    #[error("Division by zero at {0:04}")]
+    DivisionByZero(usize),
+    #[error("Invalid binary encoding: {0}")]
+    Decode(#[from] bincode::error::DecodeError),
+    #[error("Binary encoding failed: {0}")]
+    Encode(#[from] bincode::error::EncodeError),
     #[error(transparent)]
     Environment(#[from] VarError),
+    #[error("Exited at {0:04} with status {1}")]
+    Exit(usize, i32),
     #[error("Expected function call")]
     ExpectedFunctionCall,
     #[error("Expected function definition")]
@@ -26,22 +43,92 @@ pub enum Error {
     ExpectedTopLevelStatement,
     #[error("Expected value")]
     ExpectedValue,
+    #[error("Fuel exhausted at {0:04}")]
+    FuelExhausted(usize),
     #[error("Function already defined: {0}")]
     FunctionAlreadyDefined(Box<str>),
     #[error("Function definition can only happen at the top level")]
     FunctionDefinitionTopLevelOnly,
+    #[error("Incompatible binary: format version {0}, expected {FORMAT_VERSION}")]
+    IncompatibleBinary(u32),
+    #[error("Invalid binary: {0}")]
+    InvalidBinary(Box<str>),
+    #[error("Invalid constant: {0}")]
+    InvalidConstant(usize),
     #[error("Invalid label: {0}")]
     InvalidLabel(Box<str>),
+    #[error("Invalid arity for main: expected no parameters or a single one")]
+    InvalidMainArity,
+    #[error("Invalid shift count at {0:04}: {1}")]
+    InvalidShift(usize, i64),
     #[error("Invalid symbol: {0}")]
     InvalidSymbol(Box<str>),
     #[error("Invalid system call: {0}")]
     InvalidSystemCall(Box<str>),
     #[error(transparent)]
     Io(#[from] std::io::Error),
+    #[error("{1}")]
+    Located(Span, Box<Error>),
     #[error("Main endpoint not defined")]
     MainNotDefined,
+    #[error("{1}")]
+    Module(Box<str>, Box<Error>),
+    #[error("Missing binary section: {0}")]
+    MissingSection(Box<str>),
+    #[error("No program is running")]
+    NotRunning,
     #[error("Parse error: {0}")]
     Parse(String),
+    #[error("Stack overflow at {0:04}")]
+    StackOverflow(usize),
+    #[error("System call denied at {0:04}: {1}")]
+    SystemCallDenied(usize, Box<str>),
+    #[error("Type mismatch at {0:04} ({1:?}): expected {2}, got {3}")]
+    TypeMismatch(usize, OpCode, Kind, Kind),
+    #[error("Uncaught exception at {0:04}: {1}")]
+    UncaughtException(usize, Box<str>),
+    #[error("Unknown system call at {0:04}: {1}")]
+    UnknownSystemCall(usize, u32),
     #[error("Unresolved symbol: {0}")]
     UnresolvedSymbol(Box<str>),
 }
+
+impl Error {
+    pub fn at(self, span: Span) -> Self {
+        match self {
+            //
+            // Keep the innermost location, it is the most precise.
+            //
+            Error::Located(..) | Error::Module(..) => self,
+            _ => Error::Located(span, Box::new(self)),
+        }
+    }
+}
+
+impl<T: std::fmt::Display> From<ParseError<usize, T, Error>> for Error {
+    fn from(value: ParseError<usize, T, Error>) -> Self {
+        match value {
+            ParseError::InvalidToken { location } => {
+                Error::Parse("Invalid token".into()).at(Span::new(location, location + 1))
+            }
+            ParseError::UnrecognizedEof { location, expected } => {
+                let error = format!("Unexpected end of file, expected {}", expected.join(" or "));
+                Error::Parse(error).at(Span::new(location, location))
+            }
+            ParseError::UnrecognizedToken {
+                token: (start, token, end),
+                expected,
+            } => {
+                let error = format!(
+                    "Unexpected token `{token}`, expected {}",
+                    expected.join(" or ")
+                );
+                Error::Parse(error).at(Span::new(start, end))
+            }
+            ParseError::ExtraToken {
+                token: (start, token, end),
+            } => Error::Parse(format!("Extra token `{token}`")).at(Span::new(start, end)),
+            ParseError::User { error } => error,
+        }
+    }
+}
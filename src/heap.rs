@@ -1,41 +1,655 @@
-use std::rc::Rc;
+use std::{borrow::Cow, collections::HashMap, rc::Rc};
 
-use crate::{opcodes::Immediate, stack};
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+
+use crate::{
+    opcodes::{Arity, Immediate},
+    stack::{self, Kind},
+};
+
+//
+// Default collection threshold.
+//
+
+const DEFAULT_THRESHOLD: usize = 1024;
+
+//
+// Handle.
+//
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Handle(u32);
+
+impl Handle {
+    const fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+//
+// Closure.
+//
+
+#[derive(Clone, Debug)]
+pub struct Closure {
+    args: usize,
+    vals: Box<[stack::Value]>,
+}
+
+impl Closure {
+    pub fn new(args: usize, vals: Box<[stack::Value]>) -> Self {
+        Self { args, vals }
+    }
+
+    pub fn args(&self) -> usize {
+        self.args
+    }
+
+    pub fn values(&self) -> &[stack::Value] {
+        &self.vals
+    }
+}
+
+//
+// String.
+//
+
+#[derive(Clone, Debug)]
+pub struct Str {
+    bytes: Rc<[u8]>,
+    start: usize,
+}
+
+impl Str {
+    pub fn new(bytes: Rc<[u8]>) -> Option<Self> {
+        //
+        // Empty strings are nil.
+        //
+        (!bytes.is_empty()).then_some(Self { bytes, start: 0 })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[self.start..]
+    }
+
+    pub fn first(&self) -> char {
+        self.split().0
+    }
+
+    pub fn rest(&self) -> Option<Self> {
+        //
+        // The tail shares the bytes of the string.
+        //
+        let start = self.start + self.split().1;
+        (start < self.bytes.len()).then(|| Self {
+            bytes: self.bytes.clone(),
+            start,
+        })
+    }
+
+    fn split(&self) -> (char, usize) {
+        //
+        // A character is at most 4 bytes long, so only decode these.
+        //
+        let bytes = self.as_bytes();
+        let bytes = &bytes[..bytes.len().min(4)];
+        let mut chars = char_indices(bytes);
+        let (_, first) = chars.next().expect("Strings are not empty");
+        let width = chars.next().map_or(bytes.len(), |(i, _)| i);
+        (first, width)
+    }
+}
+
+//
+// Decode UTF-8 bytes, replacing the invalid sequences.
+//
+
+pub fn char_indices(bytes: &[u8]) -> impl Iterator<Item = (usize, char)> + '_ {
+    let mut offset = 0;
+    bytes.utf8_chunks().flat_map(move |chunk| {
+        let start = offset;
+        let valid = chunk.valid();
+        let invalid = chunk.invalid();
+        offset += valid.len() + invalid.len();
+        valid
+            .char_indices()
+            .map(move |(i, v)| (start + i, v))
+            .chain(
+                (!invalid.is_empty()).then_some((start + valid.len(), char::REPLACEMENT_CHARACTER)),
+            )
+    })
+}
 
 //
 // Value.
 //
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug)]
 pub enum Value {
-    Closure(stack::Closure),
+    BigNum(BigInt),
+    Closure(Closure),
     Immediate(Immediate),
-    Pair(Rc<Value>, Rc<Value>),
+    Pair(Handle, Handle),
+    String(Str),
+}
+
+//
+// Statistics.
+//
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Stats {
+    pub allocations: usize,
+    pub collections: usize,
+    pub freed: usize,
+    pub live: usize,
+}
+
+//
+// Cell.
+//
+
+#[derive(Debug, Default)]
+struct Cell {
+    marked: bool,
+    value: Option<Value>,
+}
+
+//
+// Heap.
+//
+
+#[derive(Debug)]
+pub struct Heap {
+    cells: Vec<Cell>,
+    free: Vec<Handle>,
+    pending: usize,
+    stats: Stats,
+    symbols: Vec<Box<str>>,
+    symbol_ids: HashMap<Box<str>, u32>,
+    threshold: usize,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new(DEFAULT_THRESHOLD)
+    }
+}
+
+impl Heap {
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            cells: Vec::new(),
+            free: Vec::new(),
+            pending: 0,
+            stats: Stats::default(),
+            symbols: Vec::new(),
+            symbol_ids: HashMap::new(),
+            threshold,
+        }
+    }
+
+    pub fn alloc(&mut self, value: Value) -> Handle {
+        //
+        // Update the statistics.
+        //
+        self.pending += 1;
+        self.stats.allocations += 1;
+        self.stats.live += 1;
+        //
+        // Reuse a free cell if there is any.
+        //
+        if let Some(handle) = self.free.pop() {
+            self.cells[handle.index()].value = Some(value);
+            return handle;
+        }
+        //
+        // Otherwise, grow the heap.
+        //
+        let handle = Handle(self.cells.len() as u32);
+        self.cells.push(Cell {
+            marked: false,
+            value: Some(value),
+        });
+        handle
+    }
+
+    pub fn get(&self, handle: Handle) -> &Value {
+        match &self.cells[handle.index()].value {
+            Some(value) => value,
+            None => panic!("Dangling heap handle: {handle:?}"),
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    pub fn intern(&mut self, name: &str) -> u32 {
+        //
+        // Reuse the identifier of known symbols.
+        //
+        if let Some(id) = self.symbol_ids.get(name) {
+            return *id;
+        }
+        //
+        // Otherwise, append the symbol to the table. Symbols are never
+        // collected.
+        //
+        let id = self.symbols.len() as u32;
+        self.symbols.push(name.into());
+        self.symbol_ids.insert(name.into(), id);
+        id
+    }
+
+    pub fn symbol(&self, id: u32) -> &str {
+        &self.symbols[id as usize]
+    }
+
+    pub fn bytes(&self, handle: Handle) -> Option<Cow<'_, [u8]>> {
+        //
+        // Native strings are returned as is.
+        //
+        if let Value::String(v) = self.get(handle) {
+            return Some(Cow::Borrowed(v.as_bytes()));
+        }
+        //
+        // Lists must only contain characters.
+        //
+        let mut result = Vec::new();
+        let mut next = handle;
+        while let Value::Pair(car, cdr) = self.get(next) {
+            let Value::Immediate(Immediate::Char(v)) = self.get(*car) else {
+                return None;
+            };
+            result.extend_from_slice(v.encode_utf8(&mut [0; 4]).as_bytes());
+            next = *cdr;
+        }
+        //
+        // Lists must be terminated by nil or by a native string.
+        //
+        match self.get(next) {
+            Value::Immediate(Immediate::Nil) => Some(Cow::Owned(result)),
+            Value::String(v) => {
+                result.extend_from_slice(v.as_bytes());
+                Some(Cow::Owned(result))
+            }
+            _ => None,
+        }
+    }
+}
+
+//
+// Conversions.
+//
+
+impl Heap {
+    pub fn load(&self, handle: Handle) -> stack::Value {
+        match self.get(handle) {
+            Value::Closure(_) => stack::Value::Closure(handle),
+            Value::Immediate(v) => stack::Value::Immediate(*v),
+            _ => stack::Value::Heap(handle),
+        }
+    }
+
+    pub fn store(&mut self, value: stack::Value) -> Option<Handle> {
+        match value {
+            stack::Value::Closure(v) | stack::Value::Heap(v) => Some(v),
+            stack::Value::Immediate(v) => Some(self.alloc(Value::Immediate(v))),
+            stack::Value::Link(_) => None,
+        }
+    }
+
+    pub fn kind(&self, value: &stack::Value) -> Kind {
+        match value {
+            stack::Value::Closure(_) => Kind::Closure,
+            stack::Value::Heap(v) => match self.get(*v) {
+                Value::BigNum(_) => Kind::BigNum,
+                Value::Closure(_) => Kind::Closure,
+                Value::Immediate(v) => (*v).into(),
+                Value::Pair(..) => Kind::Pair,
+                Value::String(_) => Kind::String,
+            },
+            stack::Value::Immediate(v) => (*v).into(),
+            stack::Value::Link(_) => Kind::Link,
+        }
+    }
 }
 
-impl Value {
-    pub fn iter(&self) -> ValueIterator {
-        ValueIterator(Rc::new(self.clone()))
+//
+// Equality.
+//
+
+//
+// Integers and floats are equal if they are equal as floats, like the
+// ordering operators compare them.
+//
+
+fn equal_numbers(a: &Value, b: &Value) -> Option<bool> {
+    let float = |v: &Value| match v {
+        Value::BigNum(v) => v.to_f64(),
+        Value::Immediate(Immediate::Number(v)) => Some(*v as f64),
+        _ => None,
+    };
+    match (a, b) {
+        (Value::Immediate(Immediate::Float(a)), b) => float(b).map(|b| *a == b),
+        (a, Value::Immediate(Immediate::Float(b))) => float(a).map(|a| a == *b),
+        _ => None,
+    }
+}
+
+impl Heap {
+    pub fn equal(&self, a: &stack::Value, b: &stack::Value) -> bool {
+        match (a, b) {
+            (stack::Value::Closure(a), stack::Value::Closure(b))
+            | (stack::Value::Heap(a), stack::Value::Heap(b)) => self.equal_handles(*a, *b),
+            (stack::Value::Immediate(a), stack::Value::Immediate(b)) => {
+                equal_numbers(&Value::Immediate(*a), &Value::Immediate(*b)).unwrap_or(a == b)
+            }
+            (stack::Value::Immediate(a), stack::Value::Heap(b)) => {
+                equal_numbers(&Value::Immediate(*a), self.get(*b)).unwrap_or(false)
+            }
+            (stack::Value::Heap(a), stack::Value::Immediate(b)) => {
+                equal_numbers(self.get(*a), &Value::Immediate(*b)).unwrap_or(false)
+            }
+            (stack::Value::Link(a), stack::Value::Link(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    fn equal_handles(&self, a: Handle, b: Handle) -> bool {
+        //
+        // Identical handles are always equal.
+        //
+        if a == b {
+            return true;
+        }
+        //
+        // Otherwise compare the values.
+        //
+        match (self.get(a), self.get(b)) {
+            (Value::BigNum(a), Value::BigNum(b)) => a == b,
+            (Value::Closure(a), Value::Closure(b)) => {
+                a.args == b.args
+                    && a.vals.len() == b.vals.len()
+                    && a.vals
+                        .iter()
+                        .zip(b.vals.iter())
+                        .all(|(a, b)| self.equal(a, b))
+            }
+            (a, b) if let Some(v) = equal_numbers(a, b) => v,
+            (Value::Immediate(a), Value::Immediate(b)) => a == b,
+            (Value::Pair(a0, a1), Value::Pair(b0, b1)) => {
+                self.equal_handles(*a0, *b0) && self.equal_handles(*a1, *b1)
+            }
+            //
+            // Strings are equal to the lists of the same characters.
+            //
+            (Value::String(_), Value::Pair(..) | Value::String(_))
+            | (Value::Pair(..), Value::String(_)) => match (self.bytes(a), self.bytes(b)) {
+                (Some(a), Some(b)) => a == b,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+//
+// Garbage collection.
+//
+
+impl Heap {
+    pub fn should_collect(&self) -> bool {
+        self.pending >= self.threshold
+    }
+
+    pub fn collect<'a>(&mut self, roots: impl Iterator<Item = &'a stack::Value>) {
+        //
+        // Mark the cells reachable from the roots.
+        //
+        let mut work: Vec<_> = roots
+            .filter_map(|v| match v {
+                stack::Value::Closure(v) | stack::Value::Heap(v) => Some(*v),
+                _ => None,
+            })
+            .collect();
+        while let Some(handle) = work.pop() {
+            let cell = &mut self.cells[handle.index()];
+            //
+            // Skip the cell if it has already been visited.
+            //
+            if cell.marked {
+                continue;
+            }
+            cell.marked = true;
+            //
+            // Track the children of the cell.
+            //
+            match &cell.value {
+                Some(Value::Closure(v)) => {
+                    work.extend(v.vals.iter().filter_map(|v| match v {
+                        stack::Value::Closure(v) | stack::Value::Heap(v) => Some(*v),
+                        _ => None,
+                    }));
+                }
+                Some(Value::Pair(car, cdr)) => {
+                    work.push(*car);
+                    work.push(*cdr);
+                }
+                _ => (),
+            }
+        }
+        //
+        // Sweep the unmarked cells.
+        //
+        let mut freed = 0;
+        for (index, cell) in self.cells.iter_mut().enumerate() {
+            if cell.marked {
+                cell.marked = false;
+            } else if cell.value.take().is_some() {
+                self.free.push(Handle(index as u32));
+                freed += 1;
+            }
+        }
+        //
+        // Update the statistics.
+        //
+        self.pending = 0;
+        self.stats.collections += 1;
+        self.stats.freed += freed;
+        self.stats.live -= freed;
+        //
+        // Grow the threshold with the live set.
+        //
+        self.threshold = self.threshold.max(self.stats.live);
     }
 }
 
 //
-// Value iterator.
+// Iterator.
 //
 
-pub struct ValueIterator(Rc<Value>);
+impl Heap {
+    pub fn iter(&self, handle: Handle) -> ValueIterator<'_> {
+        ValueIterator(self, Some(handle))
+    }
+}
 
-impl std::iter::Iterator for ValueIterator {
-    type Item = Rc<Value>;
+pub struct ValueIterator<'a>(&'a Heap, Option<Handle>);
+
+impl<'a> std::iter::Iterator for ValueIterator<'a> {
+    type Item = &'a Value;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.0.as_ref() {
+        match self.0.get(self.1?) {
             Value::Pair(car, cdr) => {
-                let result = car.clone();
-                self.0 = cdr.clone();
-                Some(result)
+                self.1 = Some(*cdr);
+                Some(self.0.get(*car))
             }
             _ => None,
         }
     }
 }
+
+//
+// Dump.
+//
+
+pub struct Dump<'a>(pub &'a Heap, pub &'a stack::Value);
+
+impl std::fmt::Debug for Dump<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.1 {
+            stack::Value::Closure(v) => write!(f, "Closure({:?})", CellDump(self.0, *v)),
+            stack::Value::Heap(v) => write!(f, "Heap({:?})", CellDump(self.0, *v)),
+            v => write!(f, "{v:?}"),
+        }
+    }
+}
+
+struct CellDump<'a>(&'a Heap, Handle);
+
+impl std::fmt::Debug for CellDump<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0.get(self.1) {
+            Value::BigNum(v) => write!(f, "BigNum({v})"),
+            Value::Closure(v) => {
+                let vals: Vec<_> = v.vals.iter().map(|v| Dump(self.0, v)).collect();
+                write!(f, "Closure {{ args: {}, vals: {vals:?} }}", v.args)
+            }
+            Value::Immediate(v) => write!(f, "Immediate({v:?})"),
+            Value::Pair(car, cdr) => write!(
+                f,
+                "Pair({:?}, {:?})",
+                CellDump(self.0, *car),
+                CellDump(self.0, *cdr)
+            ),
+            Value::String(v) => write!(f, "String({:?})", String::from_utf8_lossy(v.as_bytes())),
+        }
+    }
+}
+
+//
+// Printer.
+//
+
+pub struct Printer<'a>(pub &'a Heap, pub &'a stack::Value);
+
+impl std::fmt::Display for Printer<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.1 {
+            stack::Value::Closure(v) | stack::Value::Heap(v) => Self::cell(f, self.0, *v),
+            stack::Value::Immediate(v) => Self::immediate(f, self.0, v),
+            stack::Value::Link(v) => write!(f, "#<link {v:04}>"),
+        }
+    }
+}
+
+impl Printer<'_> {
+    fn cell(f: &mut std::fmt::Formatter<'_>, heap: &Heap, handle: Handle) -> std::fmt::Result {
+        match heap.get(handle) {
+            Value::BigNum(v) => write!(f, "{v}"),
+            Value::Closure(v) => Self::closure(f, v),
+            Value::Immediate(v) => Self::immediate(f, heap, v),
+            Value::Pair(..) if let Some(v) = heap.bytes(handle) => Self::string(f, &v),
+            Value::Pair(car, cdr) => {
+                //
+                // Print the first element.
+                //
+                write!(f, "(")?;
+                Self::cell(f, heap, *car)?;
+                //
+                // Print the remaining elements.
+                //
+                let mut next = *cdr;
+                while let Value::Pair(car, cdr) = heap.get(next) {
+                    write!(f, " ")?;
+                    Self::cell(f, heap, *car)?;
+                    next = *cdr;
+                }
+                //
+                // Print the tail of dotted pairs.
+                //
+                if !matches!(heap.get(next), Value::Immediate(Immediate::Nil)) {
+                    write!(f, " . ")?;
+                    Self::cell(f, heap, next)?;
+                }
+                write!(f, ")")
+            }
+            Value::String(v) => Self::string(f, v.as_bytes()),
+        }
+    }
+
+    fn closure(f: &mut std::fmt::Formatter<'_>, closure: &Closure) -> std::fmt::Result {
+        //
+        // The funcall is the last value of the closure.
+        //
+        let Some(stack::Value::Immediate(Immediate::Funcall(addr, arity))) = closure.vals.last()
+        else {
+            return write!(f, "#<closure>");
+        };
+        //
+        // Print the address and the number of missing arguments.
+        //
+        write!(f, "#<closure {addr:04}/")?;
+        Self::arity(f, *arity, closure.args)?;
+        write!(f, ">")
+    }
+
+    fn arity(f: &mut std::fmt::Formatter<'_>, arity: Arity, args: usize) -> std::fmt::Result {
+        match arity {
+            Arity::All => write!(f, "*"),
+            Arity::Some(v) => write!(f, "{}", (v as usize).saturating_sub(args)),
+            Arity::SomeWithRem(v) => write!(f, "{}+", (v as usize).saturating_sub(args)),
+            Arity::None => write!(f, "0"),
+        }
+    }
+
+    fn string(f: &mut std::fmt::Formatter<'_>, bytes: &[u8]) -> std::fmt::Result {
+        write!(f, "\"")?;
+        char_indices(bytes).try_for_each(|(_, v)| match v {
+            '"' => write!(f, "\\\""),
+            v => Self::escaped(f, v),
+        })?;
+        write!(f, "\"")
+    }
+
+    fn escaped(f: &mut std::fmt::Formatter<'_>, value: char) -> std::fmt::Result {
+        match value {
+            '\0' => write!(f, "\\0"),
+            '\x1B' => write!(f, "\\e"),
+            '\n' => write!(f, "\\n"),
+            '\r' => write!(f, "\\r"),
+            '\t' => write!(f, "\\t"),
+            '\\' => write!(f, "\\\\"),
+            v if v.is_control() => write!(f, "\\u{{{:x}}}", v as u32),
+            v => write!(f, "{v}"),
+        }
+    }
+
+    fn immediate(
+        f: &mut std::fmt::Formatter<'_>,
+        heap: &Heap,
+        value: &Immediate,
+    ) -> std::fmt::Result {
+        match value {
+            Immediate::Nil => write!(f, "nil"),
+            Immediate::True => write!(f, "T"),
+            Immediate::Char(v) => {
+                write!(f, "^")?;
+                Self::escaped(f, *v)
+            }
+            Immediate::Number(v) => write!(f, "{v}"),
+            Immediate::Float(v) => write!(f, "{v:?}"),
+            Immediate::Funcall(v, arity) => {
+                write!(f, "#<function {v:04}/")?;
+                Self::arity(f, *arity, 0)?;
+                write!(f, ">")
+            }
+            Immediate::Syscall(v, _) => write!(f, "#<syscall {v}>"),
+            Immediate::Symbol(v) => write!(f, "{}", heap.symbol(*v)),
+        }
+    }
+}
@@ -1,8 +1,13 @@
 use std::{collections::BTreeSet, fmt::Display, rc::Rc, str::FromStr};
 
+use num_bigint::BigInt;
 use strum_macros::EnumString;
 
-use crate::{atom::Atom, error::Error, opcodes::Arity};
+use crate::{
+    atom::{Atom, Span},
+    error::Error,
+    opcodes::Arity,
+};
 
 //
 // Arguments.
@@ -56,14 +61,14 @@ impl Arguments {
         /*
          * Split the atom.
          */
-        let Atom::Pair(car, cdr) = atom.as_ref() else {
-            return Err(Error::ExpectedPair);
+        let Atom::Pair(car, cdr, _) = atom.as_ref() else {
+            return Err(Error::ExpectedPair.at(atom.span()));
         };
         /*
          * Make sure CAR is a symbol.
          */
-        let Atom::Symbol(symbol) = car.as_ref() else {
-            return Err(Error::ExpectedSymbol);
+        let Atom::Symbol(symbol, _) = car.as_ref() else {
+            return Err(Error::ExpectedSymbol.at(car.span()));
         };
         /*
          * Save the symbol.
@@ -73,10 +78,10 @@ impl Arguments {
          * Check CDR.
          */
         match cdr.as_ref() {
-            Atom::Nil => Ok(Self::List(syms)),
+            Atom::Nil(_) => Ok(Self::List(syms)),
             Atom::Pair(..) => Self::from_pair(cdr.clone(), syms),
-            Atom::Symbol(v) => Ok(Self::ListAndCapture(syms, v.clone())),
-            _ => Err(Error::ExpectedPairOrSymbol),
+            Atom::Symbol(v, _) => Ok(Self::ListAndCapture(syms, v.clone())),
+            _ => Err(Error::ExpectedPairOrSymbol.at(cdr.span())),
         }
     }
 }
@@ -86,10 +91,10 @@ impl TryFrom<Rc<Atom>> for Arguments {
 
     fn try_from(value: Rc<Atom>) -> Result<Self, Error> {
         match value.as_ref() {
-            Atom::Nil => Ok(Arguments::None),
+            Atom::Nil(_) => Ok(Arguments::None),
             Atom::Pair(..) => Self::from_pair(value, Vec::new()),
-            Atom::Symbol(v) => Ok(Arguments::Capture(v.clone())),
-            _ => Err(Error::ExpectedPairOrSymbol),
+            Atom::Symbol(v, _) => Ok(Arguments::Capture(v.clone())),
+            _ => Err(Error::ExpectedPairOrSymbol.at(value.span())),
         }
     }
 }
@@ -107,6 +112,14 @@ pub enum Operator {
     Add,
     #[strum(serialize = "-")]
     Sub,
+    #[strum(serialize = "*")]
+    Mul,
+    #[strum(serialize = "/")]
+    Div,
+    #[strum(serialize = "%")]
+    Mod,
+    #[strum(serialize = "neg")]
+    Neg,
     #[strum(serialize = ">=")]
     Ge,
     #[strum(serialize = ">")]
@@ -116,6 +129,19 @@ pub enum Operator {
     #[strum(serialize = "<")]
     Lt,
     //
+    // Bitwise operations.
+    //
+    #[strum(serialize = "&")]
+    Band,
+    #[strum(serialize = "|")]
+    Bor,
+    #[strum(serialize = "xor")]
+    Bxor,
+    #[strum(serialize = "<<")]
+    Shl,
+    #[strum(serialize = ">>")]
+    Shr,
+    //
     // Logic.
     //
     #[strum(serialize = "and")]
@@ -138,15 +164,27 @@ pub enum Operator {
     #[strum(serialize = "cons")]
     Cons,
     //
-    // String operation.
+    // String operations.
     //
     #[strum(serialize = "str")]
     Str,
+    #[strum(serialize = "len")]
+    Len,
+    #[strum(serialize = "cat")]
+    Cat,
+    #[strum(serialize = "substr")]
+    Substr,
+    #[strum(serialize = "cmp")]
+    Cmp,
+    #[strum(serialize = "find")]
+    Find,
     //
     // Predicates.
     //
     #[strum(serialize = "chr?")]
     IsChr,
+    #[strum(serialize = "flt?")]
+    IsFlt,
     #[strum(serialize = "num?")]
     IsNum,
     #[strum(serialize = "lst?")]
@@ -164,10 +202,19 @@ impl Operator {
         match self {
             Operator::Add => 2,
             Operator::Sub => 2,
+            Operator::Mul => 2,
+            Operator::Div => 2,
+            Operator::Mod => 2,
+            Operator::Neg => 1,
             Operator::Ge => 2,
             Operator::Gt => 2,
             Operator::Le => 2,
             Operator::Lt => 2,
+            Operator::Band => 2,
+            Operator::Bor => 2,
+            Operator::Bxor => 2,
+            Operator::Shl => 2,
+            Operator::Shr => 2,
             Operator::And => 2,
             Operator::Equ => 2,
             Operator::Neq => 2,
@@ -177,7 +224,13 @@ impl Operator {
             Operator::Cdr => 1,
             Operator::Cons => 2,
             Operator::Str => 1,
+            Operator::Len => 1,
+            Operator::Cat => 2,
+            Operator::Substr => 3,
+            Operator::Cmp => 2,
+            Operator::Find => 2,
             Operator::IsChr => 1,
+            Operator::IsFlt => 1,
             Operator::IsNum => 1,
             Operator::IsLst => 1,
             Operator::IsNil => 1,
@@ -191,14 +244,17 @@ impl Operator {
 // Value.
 //
 
-#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub enum Value {
     #[default]
     Nil,
     True,
-    Char(u8),
+    Char(char),
     Number(i64),
+    BigNum(BigInt),
+    Float(f64),
     Pair(Box<Value>, Box<Value>),
+    String(Box<str>),
     Symbol(Box<str>),
 }
 
@@ -213,9 +269,12 @@ impl Display for Value {
         match self {
             Value::Nil => write!(f, "nil"),
             Value::True => write!(f, "T"),
-            Value::Char(c) => write!(f, "{}", *c as char),
+            Value::Char(c) => write!(f, "{c}"),
             Value::Number(v) => write!(f, "{v}"),
+            Value::BigNum(v) => write!(f, "{v}"),
+            Value::Float(v) => write!(f, "{v:?}"),
             Value::Pair(..) => write!(f, "[..]"),
+            Value::String(v) => write!(f, "{v:?}"),
             Value::Symbol(v) => write!(f, "{v}"),
         }
     }
@@ -226,21 +285,21 @@ impl TryFrom<Rc<Atom>> for Value {
 
     fn try_from(atom: Rc<Atom>) -> Result<Self, Self::Error> {
         match atom.as_ref() {
-            Atom::Nil => Ok(Self::Nil),
-            Atom::True => Ok(Self::True),
-            Atom::Char(v) => Ok(Self::Char(*v)),
-            Atom::Number(v) => Ok(Self::Number(*v)),
-            Atom::Pair(car, cdr) => {
+            Atom::Nil(_) => Ok(Self::Nil),
+            Atom::True(_) => Ok(Self::True),
+            Atom::Char(v, _) => Ok(Self::Char(*v)),
+            Atom::Number(v, _) => Ok(Self::Number(*v)),
+            Atom::BigNum(v, _) => Ok(Self::BigNum(v.clone())),
+            Atom::Float(v, _) => Ok(Self::Float(*v)),
+            Atom::Pair(car, cdr, _) => {
                 let car: Value = car.clone().try_into()?;
                 let cdr: Value = cdr.clone().try_into()?;
                 Ok(Self::Pair(car.into(), cdr.into()))
             }
-            Atom::String(v) => Ok(v.chars().rev().fold(Value::Nil, |mut acc, v| {
-                acc = Value::Pair(Value::Char(v as u8).into(), acc.into());
-                acc
-            })),
-            Atom::Symbol(v) => Ok(Self::Symbol(v.clone())),
-            Atom::Wildcard => todo!(),
+            Atom::String(v, _) if v.is_empty() => Ok(Self::Nil),
+            Atom::String(v, _) => Ok(Self::String(v.clone())),
+            Atom::Symbol(v, _) => Ok(Self::Symbol(v.clone())),
+            Atom::Wildcard(span) => Err(Error::ExpectedValue.at(*span)),
         }
     }
 }
@@ -280,43 +339,51 @@ pub enum Location {
 // Statement.
 //
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Statement {
     //
     // Function application.
     //
-    Apply(Box<Statement>, Statements, Location),
-    Lambda(Arguments, Statements),
+    Apply(Box<Statement>, Statements, Location, Span),
+    Lambda(Arguments, Statements, Span),
     Operator(Operator),
-    SysCall(Box<str>),
+    SysCall(Box<str>, Span),
     //
     // Control structures.
     //
+    Catch(Box<Statement>, Statements),
     IfThenElse(Box<Statement>, Box<Statement>, Option<Box<Statement>>),
     Let(Vec<(Box<str>, Statement)>, Statements),
     Prog(Statements),
+    Throw(Box<Statement>),
+    Yield(Box<Statement>),
     //
     // Symbol and value.
     //
-    Symbol(Box<str>),
+    Symbol(Box<str>, Span),
     Value(Value),
 }
 
 impl Statement {
     pub fn closure(&self) -> BTreeSet<Box<str>> {
         match self {
-            Statement::Apply(sym, stmts, _) => {
+            Statement::Apply(sym, stmts, _, _) => {
                 let mut v = sym.closure();
                 v.extend(stmts.closure());
                 v
             }
-            Statement::Lambda(args, stmts) => {
+            Statement::Lambda(args, stmts, _) => {
                 let mut v = stmts.closure();
                 args.iter().for_each(|s| {
                     v.remove(s);
                 });
                 v
             }
+            Statement::Catch(handler, stmts) => {
+                let mut v = handler.closure();
+                v.extend(stmts.closure());
+                v
+            }
             Statement::IfThenElse(cond, then, None) => {
                 let mut v = cond.closure();
                 v.extend(then.closure());
@@ -360,7 +427,8 @@ impl Statement {
                 //
                 v
             }
-            Statement::Symbol(sym) => {
+            Statement::Throw(stmt) | Statement::Yield(stmt) => stmt.closure(),
+            Statement::Symbol(sym, _) => {
                 let mut v = BTreeSet::new();
                 v.insert(sym.clone());
                 v
@@ -369,7 +437,7 @@ impl Statement {
         }
     }
 
-    fn from_pair(atom: Rc<Atom>, rem: Rc<Atom>) -> Result<Self, Error> {
+    fn from_pair(atom: Rc<Atom>, rem: Rc<Atom>, form: Span) -> Result<Self, Error> {
         //
         // Process the atom.
         //
@@ -377,7 +445,7 @@ impl Statement {
             //
             // For symbols, check its value for built-ins.
             //
-            Atom::Symbol(sym) => match sym.as_ref() {
+            Atom::Symbol(sym, span) => match sym.as_ref() {
                 //
                 // Quote.
                 //
@@ -389,8 +457,8 @@ impl Statement {
                     //
                     // Unpack the value expression.
                     //
-                    let Atom::Pair(value, cases) = rem.as_ref() else {
-                        return Err(Error::ExpectedPair);
+                    let Atom::Pair(value, cases, _) = rem.as_ref() else {
+                        return Err(Error::ExpectedPair.at(rem.span()));
                     };
                     //
                     // Parse the value.
@@ -409,13 +477,13 @@ impl Statement {
                             //
                             // Split the atom.
                             //
-                            let Atom::Pair(cond, _) = e.as_ref() else {
+                            let Atom::Pair(cond, _, _) = e.as_ref() else {
                                 return false;
                             };
                             //
                             // Check the condition.
                             //
-                            matches!(cond.as_ref(), &Atom::Wildcard)
+                            matches!(cond.as_ref(), Atom::Wildcard(_))
                         })
                         .unwrap_or(cases.len());
 
@@ -428,8 +496,8 @@ impl Statement {
                             //
                             // Split the atom.
                             //
-                            let Atom::Pair(_, expr) = atom.as_ref() else {
-                                return Err(Error::ExpectedPair);
+                            let Atom::Pair(_, expr, _) = atom.as_ref() else {
+                                return Err(Error::ExpectedPair.at(atom.span()));
                             };
                             //
                             // Parse the statement.
@@ -446,8 +514,8 @@ impl Statement {
                             //
                             // Split the atom.
                             //
-                            let Atom::Pair(cond, expr) = case.as_ref() else {
-                                return Err(Error::ExpectedPair);
+                            let Atom::Pair(cond, expr, _) = case.as_ref() else {
+                                return Err(Error::ExpectedPair.at(case.span()));
                             };
                             //
                             // Parse the condition.
@@ -460,6 +528,7 @@ impl Statement {
                                 stmt.into(),
                                 Statements::new(vec![value.clone()]),
                                 Location::Any,
+                                case.span(),
                             );
                             //
                             // Parse the expression.
@@ -485,14 +554,75 @@ impl Statement {
                     Ok(result)
                 }
                 //
+                // Exceptions: catch.
+                //
+                "catch" => {
+                    //
+                    // Unpack the handler.
+                    //
+                    let Atom::Pair(handler, stmts, _) = rem.as_ref() else {
+                        return Err(Error::ExpectedPair.at(rem.span()));
+                    };
+                    //
+                    // Parse the handler.
+                    //
+                    let handler: Statement = handler.clone().try_into()?;
+                    //
+                    // Parse the statements.
+                    //
+                    let stmts: Statements = stmts.clone().try_into()?;
+                    //
+                    // Done.
+                    //
+                    Ok(Self::Catch(handler.into(), stmts))
+                }
+                //
+                // Exceptions: throw.
+                //
+                "throw" => {
+                    //
+                    // Unpack the value.
+                    //
+                    let Atom::Pair(value, _, _) = rem.as_ref() else {
+                        return Err(Error::ExpectedPair.at(rem.span()));
+                    };
+                    //
+                    // Parse the value.
+                    //
+                    let value: Statement = value.clone().try_into()?;
+                    //
+                    // Done.
+                    //
+                    Ok(Self::Throw(value.into()))
+                }
+                //
+                // Coroutines: yield.
+                //
+                "yield" => {
+                    //
+                    // Unpack the value.
+                    //
+                    let Atom::Pair(value, _, _) = rem.as_ref() else {
+                        return Err(Error::ExpectedPair.at(rem.span()));
+                    };
+                    //
+                    // Parse the value.
+                    //
+                    let value: Statement = value.clone().try_into()?;
+                    //
+                    // Done.
+                    //
+                    Ok(Self::Yield(value.into()))
+                }
+                //
                 // Control flow: if.
                 //
                 "if" => {
                     //
                     // Unpack the condition.
                     //
-                    let Atom::Pair(cond, args) = rem.as_ref() else {
-                        return Err(Error::ExpectedPair);
+                    let Atom::Pair(cond, args, _) = rem.as_ref() else {
+                        return Err(Error::ExpectedPair.at(rem.span()));
                     };
                     //
                     // Parse the condition.
@@ -501,8 +631,8 @@ impl Statement {
                     //
                     // Unpack THEN.
                     //
-                    let Atom::Pair(then, args) = args.as_ref() else {
-                        return Err(Error::ExpectedPair);
+                    let Atom::Pair(then, args, _) = args.as_ref() else {
+                        return Err(Error::ExpectedPair.at(args.span()));
                     };
                     //
                     // Parse THEN.
@@ -512,12 +642,12 @@ impl Statement {
                     // Unpack ELSE.
                     //
                     let else_ = match args.as_ref() {
-                        Atom::Nil => None,
-                        Atom::Pair(else_, _) => {
+                        Atom::Nil(_) => None,
+                        Atom::Pair(else_, _, _) => {
                             let v = Statement::try_from(else_.clone())?;
                             Some(Box::new(v))
                         }
-                        _ => return Err(Error::ExpectedPair),
+                        _ => return Err(Error::ExpectedPair.at(args.span())),
                     };
                     //
                     // Done.
@@ -531,8 +661,8 @@ impl Statement {
                     //
                     // Split the atom.
                     //
-                    let Atom::Pair(bindings, stmts) = rem.as_ref() else {
-                        return Err(Error::ExpectedPair);
+                    let Atom::Pair(bindings, stmts, _) = rem.as_ref() else {
+                        return Err(Error::ExpectedPair.at(rem.span()));
                     };
                     //
                     // Parse the bindings.
@@ -543,14 +673,14 @@ impl Statement {
                             //
                             // Make sure the binding is a pair.
                             //
-                            let Atom::Pair(symbol, stmt) = v.as_ref() else {
-                                return Err(Error::ExpectedPair);
+                            let Atom::Pair(symbol, stmt, _) = v.as_ref() else {
+                                return Err(Error::ExpectedPair.at(v.span()));
                             };
                             //
                             // Make sure the symbol is a symbol.
                             //
-                            let Atom::Symbol(symbol) = symbol.as_ref() else {
-                                return Err(Error::ExpectedSymbol);
+                            let Atom::Symbol(symbol, _) = symbol.as_ref() else {
+                                return Err(Error::ExpectedSymbol.at(symbol.span()));
                             };
                             //
                             // Parse the statement.
@@ -574,7 +704,9 @@ impl Statement {
                 //
                 // Function definition are forbidden.
                 //
-                "def" => Err(Error::FunctionDefinitionTopLevelOnly),
+                "def" => {
+                    Err(Error::FunctionDefinitionTopLevelOnly.at(atom.span().join(rem.span())))
+                }
                 //
                 // Lambda definition.
                 //
@@ -582,8 +714,8 @@ impl Statement {
                     //
                     // Split the lambda call.
                     //
-                    let Atom::Pair(args, rem) = rem.as_ref() else {
-                        return Err(Error::ExpectedPair);
+                    let Atom::Pair(args, rem, _) = rem.as_ref() else {
+                        return Err(Error::ExpectedPair.at(rem.span()));
                     };
                     //
                     // Build the argument list.
@@ -596,7 +728,7 @@ impl Statement {
                     //
                     // Done.
                     //
-                    Ok(Self::Lambda(args, stmts))
+                    Ok(Self::Lambda(args, stmts, form))
                 }
                 //
                 // Prog.
@@ -612,24 +744,24 @@ impl Statement {
                     //
                     // Split the syscall.
                     //
-                    let Atom::Pair(sym, rem) = rem.as_ref() else {
-                        return Err(Error::ExpectedPair);
+                    let Atom::Pair(sym, rem, _) = rem.as_ref() else {
+                        return Err(Error::ExpectedPair.at(rem.span()));
                     };
                     //
                     // Make sure the first argument is a symbol
                     //
-                    let Atom::Symbol(sym) = sym.as_ref() else {
-                        return Err(Error::ExpectedSymbol);
+                    let Atom::Symbol(sym, span) = sym.as_ref() else {
+                        return Err(Error::ExpectedSymbol.at(sym.span()));
                     };
                     //
                     // Build the statements.
                     //
-                    let stmt = Box::new(Statement::SysCall(sym.clone()));
+                    let stmt = Box::new(Statement::SysCall(sym.clone(), *span));
                     let stmts: Statements = rem.clone().try_into()?;
                     //
                     // Done.
                     //
-                    Ok(Self::Apply(stmt, stmts, Location::Any))
+                    Ok(Self::Apply(stmt, stmts, Location::Any, form))
                 }
                 //
                 // Other symbols.
@@ -649,17 +781,17 @@ impl Statement {
                         //
                         // Otherwise, generate a symbol call.
                         else {
-                            Box::new(Statement::Symbol(sym.clone()))
+                            Box::new(Statement::Symbol(sym.clone(), *span))
                         };
                         //
                         // Done.
                         //
-                        Ok(Self::Apply(stmt, stmts, Location::Any))
+                        Ok(Self::Apply(stmt, stmts, Location::Any, form))
                     }
                     Err(_) => {
-                        let stmt = Box::new(Statement::Symbol(sym.clone()));
+                        let stmt = Box::new(Statement::Symbol(sym.clone(), *span));
                         let stmts: Statements = rem.clone().try_into()?;
-                        Ok(Self::Apply(stmt, stmts, Location::Any))
+                        Ok(Self::Apply(stmt, stmts, Location::Any, form))
                     }
                 },
             },
@@ -669,23 +801,37 @@ impl Statement {
             _ => {
                 let car = Box::new(Statement::try_from(atom.clone())?);
                 let cdr: Statements = rem.clone().try_into()?;
-                Ok(Self::Apply(car, cdr, Location::Any))
+                Ok(Self::Apply(car, cdr, Location::Any, form))
             }
         }
     }
 
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Statement::Apply(_, _, _, span)
+            | Statement::Lambda(_, _, span)
+            | Statement::SysCall(_, span)
+            | Statement::Symbol(_, span) => Some(*span),
+            _ => None,
+        }
+    }
+
     pub fn statements(&self) -> Box<dyn std::iter::Iterator<Item = &Statement> + '_> {
         match self {
-            Statement::Apply(statement, statements, _) => {
+            Statement::Apply(statement, statements, _, _) => {
                 let iter = Some(statement.as_ref())
                     .into_iter()
                     .chain(statements.iter());
                 Box::new(iter)
             }
-            Statement::Lambda(_, statements) => {
+            Statement::Lambda(_, statements, _) => {
                 let iter = statements.iter();
                 Box::new(iter)
             }
+            Statement::Catch(handler, statements) => {
+                let iter = Some(handler.as_ref()).into_iter().chain(statements.iter());
+                Box::new(iter)
+            }
             Statement::IfThenElse(statement, then, else_) => {
                 let iter = Some(statement.as_ref())
                     .into_iter()
@@ -706,8 +852,8 @@ impl Statement {
 
     fn identify_tail_calls(&mut self, name: &str) {
         match self {
-            Statement::Apply(stmt, _, location) => {
-                if let Statement::Symbol(v) = stmt.as_ref()
+            Statement::Apply(stmt, _, location, _) => {
+                if let Statement::Symbol(v, _) = stmt.as_ref()
                     && name == v.as_ref()
                 {
                     *location = Location::Tail;
@@ -732,7 +878,7 @@ impl Statement {
 
     pub fn is_tail_call(&self) -> bool {
         match self {
-            Statement::Apply(_, _, Location::Tail) => true,
+            Statement::Apply(_, _, Location::Tail, _) => true,
             Statement::IfThenElse(_, then, else_) => {
                 then.is_tail_call()
                     && else_
@@ -748,12 +894,12 @@ impl Statement {
 impl Display for Statement {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Statement::Apply(statement, statements, _) => {
+            Statement::Apply(statement, statements, _, _) => {
                 write!(f, "({statement}")?;
                 write!(f, "{statements}")?;
                 write!(f, ")")
             }
-            Statement::Lambda(args, statements) => {
+            Statement::Lambda(args, statements, _) => {
                 write!(f, "(\\ (")?;
                 for (i, v) in args.iter().enumerate() {
                     if i > 0 {
@@ -766,7 +912,12 @@ impl Display for Statement {
                 write!(f, ")")
             }
             Statement::Operator(v) => write!(f, "{v}"),
-            Statement::SysCall(sym) => write!(f, "(syscall {sym})"),
+            Statement::SysCall(sym, _) => write!(f, "(syscall {sym})"),
+            Statement::Catch(handler, statements) => {
+                write!(f, "(catch {handler}")?;
+                write!(f, "{statements}")?;
+                write!(f, ")")
+            }
             Statement::IfThenElse(cond, then, None) => {
                 write!(f, "(if {cond} {then})")
             }
@@ -783,7 +934,9 @@ impl Display for Statement {
                 write!(f, ")")
             }
             Statement::Prog(stmts) => write!(f, "(prog {stmts})"),
-            Statement::Symbol(symbol) => write!(f, "{symbol}"),
+            Statement::Throw(stmt) => write!(f, "(throw {stmt})"),
+            Statement::Yield(stmt) => write!(f, "(yield {stmt})"),
+            Statement::Symbol(symbol, _) => write!(f, "{symbol}"),
             Statement::Value(value) => write!(f, "{value}"),
         }
     }
@@ -794,8 +947,8 @@ impl TryFrom<Rc<Atom>> for Statement {
 
     fn try_from(atom: Rc<Atom>) -> Result<Self, Self::Error> {
         match atom.as_ref() {
-            Atom::Pair(atom, rem) => Self::from_pair(atom.clone(), rem.clone()),
-            Atom::Symbol(sym) => Ok(Self::Symbol(sym.clone())),
+            Atom::Pair(atom, rem, span) => Self::from_pair(atom.clone(), rem.clone(), *span),
+            Atom::Symbol(sym, span) => Ok(Self::Symbol(sym.clone(), *span)),
             _ => Value::try_from(atom).map(Self::Value),
         }
     }
@@ -805,7 +958,7 @@ impl TryFrom<Rc<Atom>> for Statement {
 // Statements.
 //
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 #[repr(transparent)]
 pub struct Statements(Vec<Statement>);
 
@@ -878,7 +1031,7 @@ impl TryFrom<Rc<Atom>> for Statements {
 
 pub enum TopLevelStatement {
     FunctionDefinition(FunctionDefinition),
-    Load(Statements),
+    Load(Statements, Span),
 }
 
 impl TryFrom<Rc<Atom>> for TopLevelStatement {
@@ -888,22 +1041,22 @@ impl TryFrom<Rc<Atom>> for TopLevelStatement {
         //
         // Split the function call.
         //
-        let Atom::Pair(a, b) = atom.as_ref() else {
-            return Err(Error::ExpectedFunctionCall);
+        let Atom::Pair(a, b, _) = atom.as_ref() else {
+            return Err(Error::ExpectedFunctionCall.at(atom.span()));
         };
         //
         // Get the function symbol.
         //
-        let Atom::Symbol(symbol) = a.as_ref() else {
-            return Err(Error::ExpectedSymbol);
+        let Atom::Symbol(symbol, _) = a.as_ref() else {
+            return Err(Error::ExpectedSymbol.at(a.span()));
         };
         //
         // Process the top-level statement.
         //
         match symbol.as_ref() {
             "def" => FunctionDefinition::try_from(atom).map(Self::FunctionDefinition),
-            "load" => b.clone().try_into().map(Self::Load),
-            _ => Err(Error::ExpectedTopLevelStatement),
+            "load" => b.clone().try_into().map(|v| Self::Load(v, atom.span())),
+            _ => Err(Error::ExpectedTopLevelStatement.at(a.span())),
         }
     }
 }
@@ -912,12 +1065,12 @@ impl TryFrom<Rc<Atom>> for TopLevelStatement {
 // Function definition.
 //
 
-#[derive(Debug, Eq, PartialEq)]
-pub struct FunctionDefinition(Box<str>, Arguments, Statements);
+#[derive(Debug, PartialEq)]
+pub struct FunctionDefinition(Box<str>, Arguments, Statements, Span);
 
 impl FunctionDefinition {
-    pub fn new(name: Box<str>, args: Arguments, stmts: Statements) -> Self {
-        Self(name, args, stmts)
+    pub fn new(name: Box<str>, args: Arguments, stmts: Statements, span: Span) -> Self {
+        Self(name, args, stmts, span)
     }
 
     pub fn name(&self) -> &Box<str> {
@@ -932,6 +1085,10 @@ impl FunctionDefinition {
         &self.2
     }
 
+    pub fn span(&self) -> Span {
+        self.3
+    }
+
     pub fn closure(&self) -> BTreeSet<Box<str>> {
         let mut v = self.2.closure();
         self.1.iter().for_each(|s| {
@@ -961,38 +1118,38 @@ impl TryFrom<Rc<Atom>> for FunctionDefinition {
         //
         // Split the function call.
         //
-        let Atom::Pair(def, rem) = atom.as_ref() else {
-            return Err(Error::ExpectedFunctionCall);
+        let Atom::Pair(def, rem, _) = atom.as_ref() else {
+            return Err(Error::ExpectedFunctionCall.at(atom.span()));
         };
         //
         // Get the function symbol.
         //
-        let Atom::Symbol(symbol) = def.as_ref() else {
-            return Err(Error::ExpectedSymbol);
+        let Atom::Symbol(symbol, _) = def.as_ref() else {
+            return Err(Error::ExpectedSymbol.at(def.span()));
         };
         //
         // Make sure we have a function definition.
         //
         if symbol.as_ref() != "def" {
-            return Err(Error::ExpectedFunctionDefinition);
+            return Err(Error::ExpectedFunctionDefinition.at(def.span()));
         }
         //
         // Extract the function name.
         //
-        let Atom::Pair(name, rem) = rem.as_ref() else {
-            return Err(Error::ExpectedPair);
+        let Atom::Pair(name, rem, _) = rem.as_ref() else {
+            return Err(Error::ExpectedPair.at(rem.span()));
         };
         //
         // Make sure the function name is a symbol.
         //
-        let Atom::Symbol(name) = name.as_ref() else {
-            return Err(Error::ExpectedSymbol);
+        let Atom::Symbol(name, _) = name.as_ref() else {
+            return Err(Error::ExpectedSymbol.at(name.span()));
         };
         //
         // Extract the arguments.
         //
-        let Atom::Pair(args, rem) = rem.as_ref() else {
-            return Err(Error::ExpectedPair);
+        let Atom::Pair(args, rem, _) = rem.as_ref() else {
+            return Err(Error::ExpectedPair.at(rem.span()));
         };
         //
         // Build the argument list.
@@ -1001,14 +1158,14 @@ impl TryFrom<Rc<Atom>> for FunctionDefinition {
         //
         // Check if there is a comment.
         //
-        let Atom::Pair(maybe_comment, statements) = rem.as_ref() else {
-            return Err(Error::ExpectedPair);
+        let Atom::Pair(maybe_comment, statements, _) = rem.as_ref() else {
+            return Err(Error::ExpectedPair.at(rem.span()));
         };
         //
-        // Skip the comment if any.
+        // Skip the comment if any, unless it is the only statement.
         //
-        let rem = match maybe_comment.as_ref() {
-            Atom::String(_) => statements,
+        let rem = match (maybe_comment.as_ref(), statements.as_ref()) {
+            (Atom::String(..), Atom::Pair(..)) => statements,
             _ => rem,
         };
         //
@@ -1022,6 +1179,6 @@ impl TryFrom<Rc<Atom>> for FunctionDefinition {
         //
         // Done.
         //
-        Ok(Self(name.clone(), args, stmts))
+        Ok(Self(name.clone(), args, stmts, atom.span()))
     }
 }
@@ -17,11 +17,16 @@ lalrpop_mod!(
 //
 
 pub mod atom;
+pub mod binary;
+pub mod cli;
 pub mod compiler;
+pub mod debug;
+pub mod diagnostic;
 pub mod error;
 pub mod heap;
 pub mod ir;
 pub mod opcodes;
+pub mod repl;
 pub mod stack;
 pub mod syscalls;
 pub mod vm;
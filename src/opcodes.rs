@@ -1,10 +1,12 @@
+use std::hash::{Hash, Hasher};
+
 use bincode::{Decode, Encode};
 
 //
 // Arity.
 //
 
-#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Encode, Decode)]
 #[repr(u32)]
 pub enum Arity {
     #[default]
@@ -18,15 +20,16 @@ pub enum Arity {
 // Immediate values.
 //
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+#[derive(Clone, Copy, Debug, PartialEq, Encode, Decode)]
 pub enum Immediate {
     Nil,
     True,
-    Char(u8),
+    Char(char),
     Number(i64),
+    Float(f64),
     Funcall(u32, Arity),
     Syscall(u32, u32),
-    Symbol([u8; 15]),
+    Symbol(u32),
 }
 
 impl Immediate {
@@ -55,11 +58,74 @@ impl From<bool> for Immediate {
     }
 }
 
+//
+// Constant pool cells. Pairs reference earlier cells by index, bignums are
+// stored as signed little-endian bytes, symbols are interned by name when
+// loaded.
+//
+
+#[derive(Clone, Debug, Encode, Decode)]
+pub enum Constant {
+    Immediate(Immediate),
+    Pair(u32, u32),
+    BigNum(Box<[u8]>),
+    String(Box<[u8]>),
+    Symbol(Box<str>),
+}
+
+//
+// Cells are compared bitwise so that they can key the compiler's pool: floats
+// are only equal if they have the same representation.
+//
+
+impl PartialEq for Constant {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Immediate(Immediate::Float(a)), Self::Immediate(Immediate::Float(b))) => {
+                a.to_bits() == b.to_bits()
+            }
+            (Self::Immediate(a), Self::Immediate(b)) => a == b,
+            (Self::Pair(a, b), Self::Pair(c, d)) => a == c && b == d,
+            (Self::BigNum(a), Self::BigNum(b)) => a == b,
+            (Self::String(a), Self::String(b)) => a == b,
+            (Self::Symbol(a), Self::Symbol(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Constant {}
+
+impl Hash for Constant {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Self::Immediate(v) => {
+                std::mem::discriminant(v).hash(state);
+                match v {
+                    Immediate::Nil | Immediate::True => (),
+                    Immediate::Char(v) => v.hash(state),
+                    Immediate::Number(v) => v.hash(state),
+                    Immediate::Float(v) => v.to_bits().hash(state),
+                    Immediate::Funcall(idx, arity) => (idx, arity).hash(state),
+                    Immediate::Syscall(idx, cnt) => (idx, cnt).hash(state),
+                    Immediate::Symbol(v) => v.hash(state),
+                }
+            }
+            Self::Pair(car, cdr) => (car, cdr).hash(state),
+            Self::BigNum(v) | Self::String(v) => v.hash(state),
+            Self::Symbol(v) => v.hash(state),
+        }
+    }
+}
+
+pub type Constants = Vec<Constant>;
+
 //
 // Opcodes.
 //
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+#[derive(Clone, Copy, Debug, PartialEq, Encode, Decode)]
 #[repr(u8)]
 pub enum OpCode {
     //
@@ -67,11 +133,23 @@ pub enum OpCode {
     //
     Add,
     Sub,
+    Mul,
+    Div,
+    Mod,
+    Neg,
     Ge,
     Gt,
     Le,
     Lt,
     //
+    // Bitwise operations.
+    //
+    Band,
+    Bor,
+    Bxor,
+    Shl,
+    Shr,
+    //
     // Logic operations.
     //
     And,
@@ -86,13 +164,19 @@ pub enum OpCode {
     Cdr,
     Cons,
     //
-    // String operation.
+    // String operations.
     //
     Str,
+    Len,
+    Cat,
+    Substr,
+    Cmp,
+    Find,
     //
     // Predicates.
     //
     IsChr,
+    IsFlt,
     IsLst,
     IsNil,
     IsNum,
@@ -105,11 +189,19 @@ pub enum OpCode {
     Brn(isize),
     Call(usize),
     Ret,
+    Yield,
+    //
+    // Exceptions.
+    //
+    Try(isize),
+    EndTry,
+    Throw,
     //
     // Stack operations.
     //
     Dup(usize),
     Get(usize),
+    Ldc(usize),
     Lst(usize),
     Pak(usize),
     Pop(usize),
@@ -0,0 +1,148 @@
+use std::{io::Write, rc::Rc};
+
+use crate::{
+    atom::{Atom, Span},
+    compiler::Compiler,
+    error::Error,
+    grammar::ListsParser,
+    opcodes::OpCode,
+    syscalls::Registry,
+    vm::VirtualMachine,
+};
+
+//
+// Name of the function wrapping the expressions.
+//
+
+const SCRATCH: &str = "<repl>";
+
+//
+// REPL.
+//
+
+pub struct Repl {
+    compiler: Compiler,
+    ops: Vec<OpCode>,
+}
+
+impl Repl {
+    pub fn new(registry: &Registry) -> Result<Self, Error> {
+        let mut compiler = Compiler::default().with_registry(registry);
+        let mut ops = Vec::new();
+        //
+        // Lift the operators into the compilation unit.
+        //
+        compiler.lift_operators()?;
+        compiler.extend(Vec::new(), &mut ops)?;
+        //
+        // Done.
+        //
+        Ok(Self { compiler, ops })
+    }
+
+    pub fn eval(
+        &mut self,
+        vm: &mut VirtualMachine,
+        source: &str,
+        out: &mut impl Write,
+    ) -> Result<(), Error> {
+        //
+        // Parse the forms of the input as a single list, moving the locations
+        // back to the input on error.
+        //
+        let parser = ListsParser::new();
+        let wrapped = format!("({source}\n)");
+        let forms = parser.parse(&wrapped).map_err(|e| match Error::from(e) {
+            Error::Located(span, e) => Error::Located(unwrap(span, source), e),
+            e => e,
+        })?;
+        //
+        // Evaluate the forms, if any.
+        //
+        let Some(forms) = forms.into_iter().next() else {
+            return Ok(());
+        };
+        forms
+            .iter()
+            .try_for_each(|form| self.eval_form(vm, form, out))
+            .map_err(|e| match e {
+                Error::Located(span, e) => Error::Located(unwrap(span, source), e),
+                e => e,
+            })
+    }
+
+    fn eval_form(
+        &mut self,
+        vm: &mut VirtualMachine,
+        form: Rc<Atom>,
+        out: &mut impl Write,
+    ) -> Result<(), Error> {
+        //
+        // Check if the form is a top-level statement.
+        //
+        let toplevel = match form.as_ref() {
+            Atom::Pair(car, _, _) => {
+                matches!(car.as_ref(), Atom::Symbol(v, _) if v.as_ref() == "def" || v.as_ref() == "load")
+            }
+            _ => false,
+        };
+        //
+        // Append top-level statements to the compilation unit.
+        //
+        if toplevel {
+            return self.compiler.extend(vec![form], &mut self.ops);
+        }
+        //
+        // Wrap expressions into a scratch function, dropped once evaluated so
+        // that the compilation unit does not grow with every expression.
+        //
+        let unit = self.compiler.clone();
+        let count = self.ops.len();
+        let result = self.eval_expression(vm, form, out);
+        self.compiler = unit;
+        self.ops.truncate(count);
+        vm.truncate_constants(self.compiler.constants().len());
+        result
+    }
+
+    fn eval_expression(
+        &mut self,
+        vm: &mut VirtualMachine,
+        form: Rc<Atom>,
+        out: &mut impl Write,
+    ) -> Result<(), Error> {
+        let span = form.span();
+        let main = [
+            Atom::symbol("def", span),
+            Atom::symbol(SCRATCH, span),
+            Atom::nil(span),
+            form,
+        ]
+        .into_iter()
+        .rev()
+        .fold(Atom::nil(span), |acc, v| Atom::cons(v, acc));
+        //
+        // Compile and evaluate the expression.
+        //
+        self.compiler.extend(vec![main], &mut self.ops)?;
+        let pc = self
+            .compiler
+            .symbols()
+            .iter()
+            .find_map(|(k, v, _)| (k.as_ref() == SCRATCH).then_some(*v))
+            .ok_or_else(|| Error::UnresolvedSymbol(SCRATCH.into()))?;
+        vm.load_constants(self.compiler.constants())?;
+        let value = vm.invoke(pc, &self.ops)?;
+        writeln!(out, "{}", vm.display(&value))?;
+        Ok(())
+    }
+}
+
+//
+// Move a span of the wrapped input back to the input.
+//
+
+fn unwrap(span: Span, source: &str) -> Span {
+    let clamp = |v: usize| v.saturating_sub(1).min(source.len());
+    Span::new(clamp(span.start), clamp(span.end))
+}
@@ -1,20 +1,55 @@
-use std::rc::Rc;
-
 use crate::{heap, opcodes::Immediate};
 
 //
-// Closure.
+// Kind.
 //
 
-#[derive(Clone, Debug, Eq, PartialEq)]
-pub struct Closure {
-    args: usize,
-    vals: Rc<[Value]>,
+#[derive(Clone, Copy, Debug, strum_macros::Display, Eq, PartialEq)]
+pub enum Kind {
+    #[strum(serialize = "bignum")]
+    BigNum,
+    #[strum(serialize = "callable")]
+    Callable,
+    #[strum(serialize = "char")]
+    Char,
+    #[strum(serialize = "closure")]
+    Closure,
+    #[strum(serialize = "float")]
+    Float,
+    #[strum(serialize = "funcall")]
+    Funcall,
+    #[strum(serialize = "link")]
+    Link,
+    #[strum(serialize = "nil")]
+    Nil,
+    #[strum(serialize = "number")]
+    Number,
+    #[strum(serialize = "pair")]
+    Pair,
+    #[strum(serialize = "string")]
+    String,
+    #[strum(serialize = "symbol")]
+    Symbol,
+    #[strum(serialize = "syscall")]
+    Syscall,
+    #[strum(serialize = "T")]
+    True,
+    #[strum(serialize = "value")]
+    Value,
 }
 
-impl Closure {
-    fn new(args: usize, vals: Rc<[Value]>) -> Self {
-        Self { args, vals }
+impl From<Immediate> for Kind {
+    fn from(value: Immediate) -> Self {
+        match value {
+            Immediate::Nil => Kind::Nil,
+            Immediate::True => Kind::True,
+            Immediate::Char(_) => Kind::Char,
+            Immediate::Number(_) => Kind::Number,
+            Immediate::Float(_) => Kind::Float,
+            Immediate::Funcall(..) => Kind::Funcall,
+            Immediate::Syscall(..) => Kind::Syscall,
+            Immediate::Symbol(_) => Kind::Symbol,
+        }
     }
 }
 
@@ -22,10 +57,10 @@ impl Closure {
 // Value.
 //
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Value {
-    Closure(Closure),
-    Heap(Rc<heap::Value>),
+    Closure(heap::Handle),
+    Heap(heap::Handle),
     Immediate(Immediate),
     Link(usize),
 }
@@ -64,6 +99,26 @@ impl Stack {
         Self(Vec::with_capacity(capacity))
     }
 
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn truncate(&mut self, n: usize) {
+        self.0.truncate(n);
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Value> {
+        self.0.iter()
+    }
+
     pub fn push(&mut self, v: Value) {
         self.0.push(v);
     }
@@ -89,15 +144,15 @@ impl Stack {
     pub fn dup(&mut self, n: usize) {
         let len = self.0.len();
         for i in len - n..len {
-            self.push(self.0[i].clone());
+            self.push(self.0[i]);
         }
     }
 
     pub fn get(&mut self, n: usize) {
-        self.push(self.0[self.0.len() - n].clone());
+        self.push(self.0[self.0.len() - n]);
     }
 
-    pub fn list(&mut self, n: usize) {
+    pub fn list(&mut self, heap: &mut heap::Heap, n: usize) -> Result<(), Kind> {
         //
         // Return NIL on empty list.
         //
@@ -110,24 +165,18 @@ impl Stack {
             //
             // Build the list from the N elements of the stack.
             //
-            let result = self.0[self.0.len() - n..].iter().fold(
-                Rc::new(heap::Value::Immediate(Immediate::Nil)),
-                |acc, v| {
-                    //
-                    // Get the next element.
-                    //
-                    let w = match v {
-                        Value::Closure(v) => heap::Value::Closure(v.clone()).into(),
-                        Value::Heap(v) => v.clone(),
-                        Value::Immediate(v) => heap::Value::Immediate(*v).into(),
-                        Value::Link(_) => panic!(),
-                    };
-                    //
-                    // Build the pair.
-                    //
-                    Rc::new(heap::Value::Pair(w, acc))
-                },
-            );
+            let nil = heap.alloc(heap::Value::Immediate(Immediate::Nil));
+            let items = &self.0[self.0.len() - n..];
+            let result = items.iter().try_fold(nil, |acc, v| -> Result<_, Kind> {
+                //
+                // Get the next element, return links cannot be stored.
+                //
+                let w = heap.store(*v).ok_or(Kind::Link)?;
+                //
+                // Build the pair.
+                //
+                Ok(heap.alloc(heap::Value::Pair(w, acc)))
+            })?;
             //
             // Drop the N element from the stack.
             //
@@ -139,6 +188,10 @@ impl Stack {
             //
             self.0.push(Value::Heap(result));
         }
+        //
+        // Done.
+        //
+        Ok(())
     }
 
     pub const fn rotate(&mut self, n: usize) {
@@ -167,16 +220,24 @@ impl Stack {
         }
     }
 
-    pub fn pack(&mut self, args: usize, total: usize) {
-        let v: Rc<[Value]> = self.0[self.0.len() - total..].into();
+    pub fn pack(&mut self, heap: &mut heap::Heap, args: usize, total: usize) {
+        let vals: Box<[Value]> = self.0[self.0.len() - total..].into();
+        let closure = heap::Closure::new(args, vals);
         self.drop(total);
-        self.push(Value::Closure(Closure::new(args, v)))
-    }
-
-    pub fn unpack(&mut self, closure: Closure) -> (usize, usize) {
-        let result = (closure.args, closure.vals.len());
-        self.0.extend_from_slice(closure.vals.as_ref());
-        result
+        self.push(Value::Closure(heap.alloc(heap::Value::Closure(closure))))
+    }
+
+    pub fn unpack(
+        &mut self,
+        heap: &heap::Heap,
+        closure: heap::Handle,
+    ) -> Result<(usize, usize), Kind> {
+        let heap::Value::Closure(closure) = heap.get(closure) else {
+            return Err(heap.kind(&Value::Heap(closure)));
+        };
+        let result = (closure.args(), closure.values().len());
+        self.0.extend_from_slice(closure.values());
+        Ok(result)
     }
 
     pub fn unlink(&mut self) -> Value {
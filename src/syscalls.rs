@@ -1,52 +1,734 @@
-use crate::{error::Error, heap, opcodes::Immediate, stack};
-
-pub fn call(index: u32, values: &[stack::Value]) -> stack::Value {
-    match index {
-        0 => {
-            //
-            // Get the first argument.
-            //
-            let stack::Value::Immediate(Immediate::Number(fd)) = values[1] else {
-                return stack::Value::Immediate(Immediate::Nil);
-            };
-            //
-            // Get the argument.
-            //
-            let stack::Value::Heap(value) = &values[0] else {
-                return stack::Value::Immediate(Immediate::Nil);
-            };
-            //
-            // Build the bytes.
-            //
-            let bytes: Vec<_> = value
-                .iter()
-                .filter_map(|v| match v.as_ref() {
-                    heap::Value::Immediate(Immediate::Char(v)) => Some(*v),
-                    _ => None,
-                })
-                .collect();
-            //
-            // Write.
-            //
-            let n = unsafe {
-                libc::write(
-                    fd as i32,
-                    bytes.as_ptr() as *const libc::c_void,
-                    bytes.len(),
-                )
-            };
-            //
-            // Done.
-            //
-            stack::Value::Immediate(Immediate::Number(n as i64))
-        }
-        _ => stack::Value::Immediate(Immediate::Nil),
-    }
-}
-
-pub fn get(name: &str) -> Result<(u32, u32), Error> {
-    match name {
-        "WRITE" => Ok((0, 2)),
-        _ => Err(Error::InvalidSystemCall(name.into())),
+use std::{borrow::Cow, collections::HashMap, ffi::CString, str::FromStr};
+
+use strum_macros::EnumString;
+
+use crate::{error::Error, heap, opcodes::Immediate, stack, vm::VirtualMachine};
+
+//
+// Open flags.
+//
+
+const OPEN_READ: i64 = 1;
+const OPEN_WRITE: i64 = 2;
+const OPEN_CREATE: i64 = 4;
+const OPEN_TRUNCATE: i64 = 8;
+const OPEN_APPEND: i64 = 16;
+const OPEN_EXCLUSIVE: i64 = 32;
+
+//
+// Largest read, longer reads return short.
+//
+
+const READ_LIMIT: i64 = 64 * 1024;
+
+//
+// Host functions.
+//
+// The arguments are laid out in reverse order: the last argument of the call
+// is the first value of the slice.
+//
+
+pub type HostFunction = Box<dyn Fn(&mut heap::Heap, &[stack::Value]) -> stack::Value>;
+
+type Builtin = fn(&mut heap::Heap, &[stack::Value]) -> stack::Value;
+
+const BUILTINS: [(&str, Class, u32, Builtin); 18] = [
+    ("WRITE", Class::Fs, 2, write),
+    ("ENV", Class::Env, 0, env),
+    ("GETENV", Class::Env, 1, getenv),
+    ("READ", Class::Fs, 2, read),
+    ("OPEN", Class::Fs, 3, open),
+    ("CLOSE", Class::Fs, 1, close),
+    ("LSEEK", Class::Fs, 3, lseek),
+    ("UNLINK", Class::Fs, 1, unlink),
+    ("STAT", Class::Fs, 1, stat),
+    ("MKDIR", Class::Fs, 2, mkdir),
+    ("READDIR", Class::Fs, 1, readdir),
+    ("GETPID", Class::Process, 0, getpid),
+    ("FORK", Class::Process, 0, fork),
+    ("EXECVE", Class::Process, 3, execve),
+    ("WAITPID", Class::Process, 2, waitpid),
+    ("PIPE", Class::Process, 0, pipe),
+    ("DUP2", Class::Process, 2, dup2),
+    ("KILL", Class::Process, 2, kill),
+];
+
+//
+// Functions.
+//
+// Exiting is not a host function: it stops the machine with the status of its
+// argument, and the host decides what to do with it.
+//
+
+enum Function {
+    Exit,
+    Host(HostFunction),
+}
+
+//
+// Class.
+//
+// The operations on file descriptors belong to the fs class, so that denying it
+// also denies the descriptors inherited from the host, like the standard ones.
+//
+
+#[derive(Clone, Copy, Debug, strum_macros::Display, EnumString, Eq, Hash, PartialEq)]
+pub enum Class {
+    #[strum(serialize = "env")]
+    Env,
+    #[strum(serialize = "fs")]
+    Fs,
+    #[strum(serialize = "host")]
+    Host,
+    #[strum(serialize = "network")]
+    Network,
+    #[strum(serialize = "process")]
+    Process,
+}
+
+//
+// Policy.
+//
+// Rules on names take precedence over rules on classes, which take precedence
+// over the default.
+//
+
+#[derive(Clone, Debug)]
+pub struct Policy {
+    default: bool,
+    classes: HashMap<Class, bool>,
+    names: HashMap<Box<str>, bool>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self::allow_all()
+    }
+}
+
+impl Policy {
+    pub fn allow_all() -> Self {
+        Self {
+            default: true,
+            classes: HashMap::new(),
+            names: HashMap::new(),
+        }
+    }
+
+    pub fn deny_all() -> Self {
+        Self {
+            default: false,
+            ..Self::allow_all()
+        }
+    }
+
+    pub fn allow(self, rule: &str) -> Self {
+        self.rule(rule, true)
+    }
+
+    pub fn deny(self, rule: &str) -> Self {
+        self.rule(rule, false)
+    }
+
+    pub fn permits(&self, name: &str, class: Class) -> bool {
+        self.names
+            .get(name)
+            .or_else(|| self.classes.get(&class))
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    fn rule(mut self, rule: &str, allowed: bool) -> Self {
+        match Class::from_str(rule) {
+            Ok(class) => {
+                self.classes.insert(class, allowed);
+            }
+            Err(_) => {
+                self.names.insert(rule.into(), allowed);
+            }
+        }
+        self
+    }
+}
+
+//
+// Registry.
+//
+
+pub struct Registry {
+    functions: Vec<(Box<str>, Class, u32, Function)>,
+    policy: Policy,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        let functions = BUILTINS
+            .into_iter()
+            .map(|(name, class, arity, fun)| {
+                (name.into(), class, arity, Function::Host(Box::new(fun)))
+            })
+            .chain([("EXIT".into(), Class::Process, 1, Function::Exit)])
+            .collect();
+        Self {
+            functions,
+            policy: Policy::default(),
+        }
+    }
+}
+
+impl Registry {
+    pub fn empty() -> Self {
+        Self {
+            functions: Vec::new(),
+            policy: Policy::default(),
+        }
+    }
+
+    pub fn with_policy(mut self, policy: Policy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn set_policy(&mut self, policy: Policy) {
+        self.policy = policy;
+    }
+
+    pub fn register<F>(
+        &mut self,
+        name: &str,
+        class: Class,
+        arity: u32,
+        fun: F,
+    ) -> Result<u32, Error>
+    where
+        F: Fn(&mut heap::Heap, &[stack::Value]) -> stack::Value + 'static,
+    {
+        //
+        // Make sure the function does not exist.
+        //
+        if self.functions.iter().any(|(k, ..)| k.as_ref() == name) {
+            return Err(Error::FunctionAlreadyDefined(name.into()));
+        }
+        //
+        // Append the function.
+        //
+        self.functions
+            .push((name.into(), class, arity, Function::Host(Box::new(fun))));
+        Ok(self.functions.len() as u32 - 1)
+    }
+
+    pub fn name(&self, index: u32) -> Box<str> {
+        match self.functions.get(index as usize) {
+            Some((name, ..)) => name.clone(),
+            None => index.to_string().into_boxed_str(),
+        }
+    }
+
+    pub fn call(
+        &self,
+        heap: &mut heap::Heap,
+        pc: usize,
+        index: u32,
+        values: &[stack::Value],
+    ) -> Result<stack::Value, Error> {
+        match self.functions.get(index as usize) {
+            Some((name, class, _, fun)) if self.policy.permits(name, *class) => match fun {
+                Function::Exit => Err(Error::Exit(pc, VirtualMachine::status(&values[0]))),
+                Function::Host(fun) => Ok(fun(heap, values)),
+            },
+            Some((name, ..)) => Err(Error::SystemCallDenied(pc, name.clone())),
+            None => Err(Error::UnknownSystemCall(pc, index)),
+        }
+    }
+
+    pub fn signatures(&self) -> Signatures {
+        let entries = self
+            .functions
+            .iter()
+            .enumerate()
+            .filter(|(_, (name, class, ..))| self.policy.permits(name, *class))
+            .map(|(index, (name, _, arity, _))| (name.clone(), (index as u32, *arity)))
+            .collect();
+        Signatures(entries)
+    }
+}
+
+//
+// Signatures.
+//
+
+#[derive(Clone, Debug)]
+pub struct Signatures(HashMap<Box<str>, (u32, u32)>);
+
+impl Default for Signatures {
+    fn default() -> Self {
+        Registry::default().signatures()
+    }
+}
+
+impl Signatures {
+    pub fn get(&self, name: &str) -> Result<(u32, u32), Error> {
+        self.0
+            .get(name)
+            .copied()
+            .ok_or_else(|| Error::InvalidSystemCall(name.into()))
     }
 }
+
+//
+// Environment.
+//
+
+fn env(heap: &mut heap::Heap, _: &[stack::Value]) -> stack::Value {
+    //
+    // Build the list of variables.
+    //
+    let nil = heap.alloc(heap::Value::Immediate(Immediate::Nil));
+    let list = std::env::vars_os().fold(nil, |acc, (k, v)| {
+        let k = chars(heap, k.as_encoded_bytes());
+        let v = chars(heap, v.as_encoded_bytes());
+        let pair = heap.alloc(heap::Value::Pair(k, v));
+        heap.alloc(heap::Value::Pair(pair, acc))
+    });
+    //
+    // Done.
+    //
+    heap.load(list)
+}
+
+fn getenv(heap: &mut heap::Heap, values: &[stack::Value]) -> stack::Value {
+    //
+    // Get the name of the variable.
+    //
+    let stack::Value::Heap(name) = &values[0] else {
+        return nil();
+    };
+    let name = String::from_utf8_lossy(&bytes(heap, *name)).into_owned();
+    //
+    // Look-up the variable.
+    //
+    let Some(value) = std::env::var_os(name) else {
+        return nil();
+    };
+    //
+    // Done.
+    //
+    let value = chars(heap, value.as_encoded_bytes());
+    heap.load(value)
+}
+
+//
+// File descriptors.
+//
+
+fn write(heap: &mut heap::Heap, values: &[stack::Value]) -> stack::Value {
+    //
+    // Get the file descriptor.
+    //
+    let stack::Value::Immediate(Immediate::Number(fd)) = values[1] else {
+        return nil();
+    };
+    //
+    // Get the argument.
+    //
+    let stack::Value::Heap(value) = &values[0] else {
+        return nil();
+    };
+    //
+    // Build the bytes.
+    //
+    let bytes = bytes(heap, *value);
+    //
+    // Write.
+    //
+    let n = unsafe {
+        libc::write(
+            fd as i32,
+            bytes.as_ptr() as *const libc::c_void,
+            bytes.len(),
+        )
+    };
+    //
+    // Done.
+    //
+    result(n as i64)
+}
+
+fn read(heap: &mut heap::Heap, values: &[stack::Value]) -> stack::Value {
+    //
+    // Get the file descriptor and the number of bytes to read.
+    //
+    let (Some(fd), Some(len)) = (number(&values[1]), number(&values[0])) else {
+        return nil();
+    };
+    //
+    // Read.
+    //
+    let mut buffer = vec![0_u8; len.clamp(0, READ_LIMIT) as usize];
+    let n = unsafe {
+        libc::read(
+            fd as i32,
+            buffer.as_mut_ptr() as *mut libc::c_void,
+            buffer.len(),
+        )
+    };
+    //
+    // Check for errors.
+    //
+    if n < 0 {
+        return failure();
+    }
+    //
+    // Done.
+    //
+    let value = chars(heap, &buffer[..n as usize]);
+    heap.load(value)
+}
+
+fn open(heap: &mut heap::Heap, values: &[stack::Value]) -> stack::Value {
+    //
+    // Get the arguments.
+    //
+    let (Some(path), Some(flags), Some(mode)) = (
+        path(heap, &values[2]),
+        number(&values[1]),
+        number(&values[0]),
+    ) else {
+        return nil();
+    };
+    //
+    // Convert the flags.
+    //
+    let access = match (flags & OPEN_READ != 0, flags & OPEN_WRITE != 0) {
+        (true, true) => libc::O_RDWR,
+        (false, true) => libc::O_WRONLY,
+        _ => libc::O_RDONLY,
+    };
+    let oflags = [
+        (OPEN_CREATE, libc::O_CREAT),
+        (OPEN_TRUNCATE, libc::O_TRUNC),
+        (OPEN_APPEND, libc::O_APPEND),
+        (OPEN_EXCLUSIVE, libc::O_EXCL),
+    ]
+    .into_iter()
+    .filter(|(k, _)| flags & k != 0)
+    .fold(access | libc::O_CLOEXEC, |acc, (_, v)| acc | v);
+    //
+    // Open.
+    //
+    let fd = unsafe { libc::open(path.as_ptr(), oflags, mode as libc::c_uint) };
+    //
+    // Done.
+    //
+    result(fd as i64)
+}
+
+fn close(_: &mut heap::Heap, values: &[stack::Value]) -> stack::Value {
+    let Some(fd) = number(&values[0]) else {
+        return nil();
+    };
+    result(unsafe { libc::close(fd as i32) } as i64)
+}
+
+fn lseek(_: &mut heap::Heap, values: &[stack::Value]) -> stack::Value {
+    //
+    // Get the arguments.
+    //
+    let (Some(fd), Some(offset), Some(whence)) =
+        (number(&values[2]), number(&values[1]), number(&values[0]))
+    else {
+        return nil();
+    };
+    //
+    // Convert the origin of the seek.
+    //
+    let whence = match whence {
+        0 => libc::SEEK_SET,
+        1 => libc::SEEK_CUR,
+        2 => libc::SEEK_END,
+        _ => return errno(std::io::Error::from_raw_os_error(libc::EINVAL)),
+    };
+    //
+    // Seek.
+    //
+    result(unsafe { libc::lseek(fd as i32, offset as libc::off_t, whence) } as i64)
+}
+
+//
+// File system.
+//
+
+fn unlink(heap: &mut heap::Heap, values: &[stack::Value]) -> stack::Value {
+    let Some(path) = path(heap, &values[0]) else {
+        return nil();
+    };
+    result(unsafe { libc::unlink(path.as_ptr()) } as i64)
+}
+
+fn stat(heap: &mut heap::Heap, values: &[stack::Value]) -> stack::Value {
+    //
+    // Get the path.
+    //
+    let Some(path) = path(heap, &values[0]) else {
+        return nil();
+    };
+    //
+    // Stat.
+    //
+    let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+    if unsafe { libc::stat(path.as_ptr(), &mut stat) } < 0 {
+        return failure();
+    }
+    //
+    // Build the (SIZE MODE MTIME) list.
+    //
+    let fields = [
+        stat.st_size as i64,
+        stat.st_mode as i64,
+        stat.st_mtime as i64,
+    ];
+    let nil = heap.alloc(heap::Value::Immediate(Immediate::Nil));
+    let list = fields.into_iter().rev().fold(nil, |acc, v| {
+        let v = heap.alloc(heap::Value::Immediate(Immediate::Number(v)));
+        heap.alloc(heap::Value::Pair(v, acc))
+    });
+    //
+    // Done.
+    //
+    heap.load(list)
+}
+
+fn mkdir(heap: &mut heap::Heap, values: &[stack::Value]) -> stack::Value {
+    let (Some(path), Some(mode)) = (path(heap, &values[1]), number(&values[0])) else {
+        return nil();
+    };
+    result(unsafe { libc::mkdir(path.as_ptr(), mode as libc::mode_t) } as i64)
+}
+
+fn readdir(heap: &mut heap::Heap, values: &[stack::Value]) -> stack::Value {
+    //
+    // Get the path.
+    //
+    let stack::Value::Heap(path) = &values[0] else {
+        return nil();
+    };
+    let path = String::from_utf8_lossy(&bytes(heap, *path)).into_owned();
+    //
+    // Collect the names of the entries.
+    //
+    let names: Vec<_> = match std::fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(|v| v.ok())
+            .map(|v| v.file_name())
+            .collect(),
+        Err(error) => return errno(error),
+    };
+    //
+    // Build the list of names.
+    //
+    let nil = heap.alloc(heap::Value::Immediate(Immediate::Nil));
+    let list = names.iter().rev().fold(nil, |acc, v| {
+        let v = chars(heap, v.as_encoded_bytes());
+        heap.alloc(heap::Value::Pair(v, acc))
+    });
+    //
+    // Done.
+    //
+    heap.load(list)
+}
+
+//
+// Processes.
+//
+
+fn getpid(_: &mut heap::Heap, _: &[stack::Value]) -> stack::Value {
+    stack::Value::Immediate(Immediate::Number(std::process::id() as i64))
+}
+
+fn fork(_: &mut heap::Heap, _: &[stack::Value]) -> stack::Value {
+    result(unsafe { libc::fork() } as i64)
+}
+
+fn execve(heap: &mut heap::Heap, values: &[stack::Value]) -> stack::Value {
+    //
+    // Get the path and the arguments.
+    //
+    let (Some(path), Some(argv)) = (path(heap, &values[2]), strings(heap, &values[1])) else {
+        return nil();
+    };
+    //
+    // Get the environment, inheriting the current one if it is nil.
+    //
+    let envp = match values[0] {
+        stack::Value::Immediate(Immediate::Nil) => std::env::vars_os()
+            .filter_map(|(k, v)| {
+                let mut bytes = k.into_encoded_bytes();
+                bytes.push(b'=');
+                bytes.extend(v.into_encoded_bytes());
+                CString::new(bytes).ok()
+            })
+            .collect(),
+        ref v => match strings(heap, v) {
+            Some(v) => v,
+            None => return nil(),
+        },
+    };
+    //
+    // Build the null-terminated pointer arrays.
+    //
+    let argv: Vec<_> = argv
+        .iter()
+        .map(|v| v.as_ptr())
+        .chain(std::iter::once(std::ptr::null()))
+        .collect();
+    let envp: Vec<_> = envp
+        .iter()
+        .map(|v| v.as_ptr())
+        .chain(std::iter::once(std::ptr::null()))
+        .collect();
+    //
+    // Execute, which only returns on failure.
+    //
+    unsafe { libc::execve(path.as_ptr(), argv.as_ptr(), envp.as_ptr()) };
+    failure()
+}
+
+fn waitpid(heap: &mut heap::Heap, values: &[stack::Value]) -> stack::Value {
+    //
+    // Get the arguments.
+    //
+    let (Some(pid), Some(options)) = (number(&values[1]), number(&values[0])) else {
+        return nil();
+    };
+    //
+    // Wait.
+    //
+    let mut status = 0;
+    let pid = unsafe { libc::waitpid(pid as libc::pid_t, &mut status, options as i32) };
+    if pid < 0 {
+        return failure();
+    }
+    //
+    // Decode the status like shells do.
+    //
+    let code = if libc::WIFEXITED(status) {
+        libc::WEXITSTATUS(status)
+    } else if libc::WIFSIGNALED(status) {
+        128 + libc::WTERMSIG(status)
+    } else {
+        status
+    };
+    //
+    // Build the (PID . CODE) pair.
+    //
+    let pid = heap.alloc(heap::Value::Immediate(Immediate::Number(pid as i64)));
+    let code = heap.alloc(heap::Value::Immediate(Immediate::Number(code as i64)));
+    stack::Value::Heap(heap.alloc(heap::Value::Pair(pid, code)))
+}
+
+fn pipe(heap: &mut heap::Heap, _: &[stack::Value]) -> stack::Value {
+    //
+    // Create the pipe.
+    //
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } < 0 {
+        return failure();
+    }
+    //
+    // Build the (READ . WRITE) pair.
+    //
+    let rd = heap.alloc(heap::Value::Immediate(Immediate::Number(fds[0] as i64)));
+    let wr = heap.alloc(heap::Value::Immediate(Immediate::Number(fds[1] as i64)));
+    stack::Value::Heap(heap.alloc(heap::Value::Pair(rd, wr)))
+}
+
+fn dup2(_: &mut heap::Heap, values: &[stack::Value]) -> stack::Value {
+    let (Some(old), Some(new)) = (number(&values[1]), number(&values[0])) else {
+        return nil();
+    };
+    result(unsafe { libc::dup2(old as i32, new as i32) } as i64)
+}
+
+fn kill(_: &mut heap::Heap, values: &[stack::Value]) -> stack::Value {
+    let (Some(pid), Some(sig)) = (number(&values[1]), number(&values[0])) else {
+        return nil();
+    };
+    result(unsafe { libc::kill(pid as libc::pid_t, sig as i32) } as i64)
+}
+
+//
+// Helpers.
+//
+
+fn bytes(heap: &heap::Heap, handle: heap::Handle) -> Vec<u8> {
+    heap.bytes(handle).map(Cow::into_owned).unwrap_or_default()
+}
+
+fn chars(heap: &mut heap::Heap, bytes: &[u8]) -> heap::Handle {
+    match heap::Str::new(bytes.into()) {
+        Some(v) => heap.alloc(heap::Value::String(v)),
+        None => heap.alloc(heap::Value::Immediate(Immediate::Nil)),
+    }
+}
+
+fn number(value: &stack::Value) -> Option<i64> {
+    match value {
+        stack::Value::Immediate(Immediate::Number(v)) => Some(*v),
+        _ => None,
+    }
+}
+
+fn path(heap: &heap::Heap, value: &stack::Value) -> Option<CString> {
+    match value {
+        stack::Value::Heap(v) => CString::new(bytes(heap, *v)).ok(),
+        _ => None,
+    }
+}
+
+fn strings(heap: &heap::Heap, value: &stack::Value) -> Option<Vec<CString>> {
+    let mut result = Vec::new();
+    //
+    // Get the head of the list.
+    //
+    let mut next = match value {
+        stack::Value::Heap(v) => *v,
+        stack::Value::Immediate(Immediate::Nil) => return Some(result),
+        _ => return None,
+    };
+    //
+    // Convert each element of the list.
+    //
+    while let heap::Value::Pair(car, cdr) = heap.get(next) {
+        result.push(CString::new(bytes(heap, *car)).ok()?);
+        next = *cdr;
+    }
+    //
+    // Done.
+    //
+    Some(result)
+}
+
+fn nil() -> stack::Value {
+    stack::Value::Immediate(Immediate::Nil)
+}
+
+//
+// Errors are reported as negated errno values.
+//
+
+fn result(value: i64) -> stack::Value {
+    if value < 0 {
+        failure()
+    } else {
+        stack::Value::Immediate(Immediate::Number(value))
+    }
+}
+
+fn failure() -> stack::Value {
+    errno(std::io::Error::last_os_error())
+}
+
+fn errno(error: std::io::Error) -> stack::Value {
+    let code = error.raw_os_error().unwrap_or(libc::EIO);
+    stack::Value::Immediate(Immediate::Number(-(code as i64)))
+}
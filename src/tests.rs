@@ -3,7 +3,7 @@
 //
 
 mod parser {
-    use crate::grammar::ListsParser;
+    use crate::{atom::Span, grammar::ListsParser};
 
     #[test]
     fn nil() {
@@ -21,6 +21,63 @@ mod parser {
         assert!(result[0].is_pair());
     }
 
+    #[test]
+    fn float() {
+        let parser = ListsParser::new();
+        let result = parser.parse("(1.5 -2.0e3 1e-2 3)").unwrap();
+        let values: Vec<_> = result[0].iter().map(|v| format!("{v:?}")).collect();
+        assert_eq!(
+            values,
+            vec!["float(1.5)", "float(-2000)", "float(0.01)", "number(3)"]
+        );
+    }
+
+    #[test]
+    fn bignum() {
+        let parser = ListsParser::new();
+        let result = parser
+            .parse("(99999999999999999999 -9223372036854775808)")
+            .unwrap();
+        let values: Vec<_> = result[0].iter().map(|v| format!("{v:?}")).collect();
+        assert_eq!(
+            values,
+            vec![
+                "bignum(99999999999999999999)",
+                "number(-9223372036854775808)"
+            ]
+        );
+    }
+
+    #[test]
+    fn chars() {
+        let parser = ListsParser::new();
+        let result = parser.parse(r"(^a ^( ^\n ^\\)").unwrap();
+        let values: Vec<_> = result[0].iter().map(|v| format!("{v:?}")).collect();
+        assert_eq!(values, vec!["char(97)", "char(40)", "char(10)", "char(92)"]);
+    }
+
+    #[test]
+    fn unicode() {
+        let parser = ListsParser::new();
+        let result = parser.parse(r#"(^é ^\u{1F600} "a\u{e9}\"")"#).unwrap();
+        let values: Vec<_> = result[0].iter().map(|v| format!("{v:?}")).collect();
+        assert_eq!(values, vec!["char(233)", "char(128512)", "string(aé\")"]);
+        //
+        // Escapes must be valid scalar values.
+        //
+        assert!(parser.parse(r#"("\u{D800}")"#).is_err());
+        assert!(parser.parse(r"(^\u{110000})").is_err());
+    }
+
+    #[test]
+    fn spans() {
+        let parser = ListsParser::new();
+        let result = parser.parse("(foo\n  (bar 12))").unwrap();
+        assert_eq!(result[0].span(), Span::new(0, 16));
+        let spans: Vec<_> = result[0].iter().map(|v| v.span()).collect();
+        assert_eq!(spans, vec![Span::new(1, 4), Span::new(7, 15)]);
+    }
+
     #[test]
     fn sequence_of_lists() {
         let parser = ListsParser::new();
@@ -36,7 +93,7 @@ mod parser {
 //
 
 mod ir {
-    use crate::{grammar::ListsParser, ir::FunctionDefinition};
+    use crate::{atom::Span, error::Error, grammar::ListsParser, ir::FunctionDefinition};
     use map_macro::btree_set;
 
     #[test]
@@ -53,6 +110,17 @@ mod ir {
         assert_eq!(btree_set! {"A".into(), "B".into()}, stmt.closure());
     }
 
+    #[test]
+    fn def_with_invalid_binding() {
+        let parser = ListsParser::new();
+        let atoms = parser.parse("(def test () (let ((1 . 2)) 3))").unwrap();
+        let result = FunctionDefinition::try_from(atoms[0].clone());
+        assert!(matches!(
+            result,
+            Err(Error::Located(span, _)) if span == Span::new(20, 21)
+        ));
+    }
+
     #[test]
     fn def_with_lambda_with_external_bindings() {
         let parser = ListsParser::new();
@@ -121,10 +189,12 @@ mod ir {
 
 mod compiler {
     use crate::{
+        binary::{Binary, FORMAT_VERSION},
         compiler::{Compiler, Context, LabelOrOpCode},
+        error::Error,
         grammar::ListsParser,
         ir::Statement,
-        opcodes::{Arity, Immediate, OpCode},
+        opcodes::{Arity, Constant, Immediate, OpCode},
     };
 
     #[test]
@@ -165,6 +235,54 @@ mod compiler {
         );
     }
 
+    #[test]
+    fn arithmetic_and_bitwise_builtins() {
+        let parser = ListsParser::new();
+        let atom = parser
+            .parse("(xor (* (/ 8 2) (% 7 3)) (<< (neg 1) 2))")
+            .unwrap()
+            .remove(0);
+        let stmt: Statement = atom.try_into().unwrap();
+        let mut context = Context::default();
+        let mut compiler = Compiler::default();
+        compiler.compile_statement(&mut context, &stmt).unwrap();
+        assert_eq!(
+            context.stream(),
+            &[
+                OpCode::Psh(Immediate::Number(2)).into(),
+                OpCode::Psh(Immediate::Number(1)).into(),
+                OpCode::Neg.into(),
+                OpCode::Shl.into(),
+                OpCode::Psh(Immediate::Number(3)).into(),
+                OpCode::Psh(Immediate::Number(7)).into(),
+                OpCode::Mod.into(),
+                OpCode::Psh(Immediate::Number(2)).into(),
+                OpCode::Psh(Immediate::Number(8)).into(),
+                OpCode::Div.into(),
+                OpCode::Mul.into(),
+                OpCode::Bxor.into(),
+            ]
+        );
+    }
+
+    #[test]
+    fn mixed_arithmetic_builtin() {
+        let parser = ListsParser::new();
+        let atom = parser.parse("(+ 1.5 2)").unwrap().remove(0);
+        let stmt: Statement = atom.try_into().unwrap();
+        let mut context = Context::default();
+        let mut compiler = Compiler::default();
+        compiler.compile_statement(&mut context, &stmt).unwrap();
+        assert_eq!(
+            context.stream(),
+            &[
+                OpCode::Psh(Immediate::Number(2)).into(),
+                OpCode::Psh(Immediate::Float(1.5)).into(),
+                OpCode::Add.into(),
+            ]
+        );
+    }
+
     #[test]
     fn if_then() {
         let parser = ListsParser::new();
@@ -189,6 +307,31 @@ mod compiler {
         );
     }
 
+    #[test]
+    fn catch_throw() {
+        let parser = ListsParser::new();
+        let atom = parser.parse("(catch a (throw 1))").unwrap().remove(0);
+        let stmt: Statement = atom.try_into().unwrap();
+        let mut context = Context::default();
+        let mut compiler = Compiler::default();
+        compiler.compile_statement(&mut context, &stmt).unwrap();
+        assert_eq!(
+            context.stream(),
+            &[
+                LabelOrOpCode::Get("a".to_owned().into_boxed_str()),
+                LabelOrOpCode::Try("BEGIN_CATCH_0000".to_owned().into_boxed_str()),
+                OpCode::Psh(Immediate::Number(1)).into(),
+                OpCode::Throw.into(),
+                OpCode::EndTry.into(),
+                OpCode::Rot(2).into(),
+                OpCode::Pop(1).into(),
+                LabelOrOpCode::Branch("END_CATCH_0001".to_owned().into_boxed_str()),
+                OpCode::Swp.into(),
+                OpCode::Call(1).into(),
+            ]
+        );
+    }
+
     #[test]
     fn if_then_else() {
         let parser = ListsParser::new();
@@ -367,7 +510,7 @@ mod compiler {
         assert_eq!(
             context.stream(),
             &[
-                LabelOrOpCode::Funcall("LAMBDA_0000".into()).into(),
+                LabelOrOpCode::Funcall("LAMBDA_0000".into()),
                 OpCode::Pak(1).into()
             ]
         )
@@ -378,7 +521,7 @@ mod compiler {
         let parser = ListsParser::new();
         let atoms = parser.parse("(def ADD (A B) (+ A B))").unwrap();
         let compiler = Compiler::default();
-        let (_, result) = compiler.compile(atoms).unwrap();
+        let (_, result, _) = compiler.compile(atoms).unwrap();
         assert_eq!(
             result,
             vec![
@@ -398,7 +541,7 @@ mod compiler {
         let parser = ListsParser::new();
         let atoms = parser.parse("(def ADD (A B C) (+ A B) (- A C))").unwrap();
         let compiler = Compiler::default();
-        let (_, result) = compiler.compile(atoms).unwrap();
+        let (_, result, _) = compiler.compile(atoms).unwrap();
         assert_eq!(
             result,
             vec![
@@ -429,7 +572,7 @@ mod compiler {
             )
             .unwrap();
         let compiler = Compiler::default();
-        let (_, result) = compiler.compile(atoms).unwrap();
+        let (_, result, _) = compiler.compile(atoms).unwrap();
         assert_eq!(
             result,
             vec![
@@ -467,7 +610,7 @@ mod compiler {
             .parse("(def fib (N) (if (<= N 1) N (+ (fib (- N 1)) (fib (- N 2)))))")
             .unwrap();
         let compiler = Compiler::default();
-        let (_, result) = compiler.compile(atoms).unwrap();
+        let (_, result, _) = compiler.compile(atoms).unwrap();
         assert_eq!(
             result,
             vec![
@@ -503,7 +646,7 @@ mod compiler {
             .parse("(def test(a) (let ((add . (\\ (b c) (+ b c)))) (- a (add 1 2))))")
             .unwrap();
         let compiler = Compiler::default();
-        let (_, result) = compiler.compile(atoms).unwrap();
+        let (_, result, _) = compiler.compile(atoms).unwrap();
         assert_eq!(
             result,
             vec![
@@ -552,7 +695,7 @@ mod compiler {
             )
             .unwrap();
         let compiler = Compiler::default();
-        let (_, result) = compiler.compile(atoms).unwrap();
+        let (_, result, _) = compiler.compile(atoms).unwrap();
         assert_eq!(
             result,
             vec![
@@ -602,7 +745,7 @@ mod compiler {
         let parser = ListsParser::new();
         let atoms = parser.parse("(def test() (test))").unwrap();
         let compiler = Compiler::default();
-        let (_, result) = compiler.compile(atoms).unwrap();
+        let (_, result, _) = compiler.compile(atoms).unwrap();
         assert_eq!(result, vec![OpCode::Br(0)]);
     }
 
@@ -619,7 +762,7 @@ mod compiler {
             )
             .unwrap();
         let compiler = Compiler::default();
-        let (_, result) = compiler.compile(atoms).unwrap();
+        let (_, result, _) = compiler.compile(atoms).unwrap();
         assert_eq!(
             result,
             vec![
@@ -639,7 +782,7 @@ mod compiler {
             .parse("(def test (a b) (if a (test (cdr a) (cdr b))))")
             .unwrap();
         let compiler = Compiler::default();
-        let (_, result) = compiler.compile(atoms).unwrap();
+        let (_, result, _) = compiler.compile(atoms).unwrap();
         assert_eq!(
             result,
             vec![
@@ -676,7 +819,7 @@ mod compiler {
             )
             .unwrap();
         let compiler = Compiler::default();
-        let (_, result) = compiler.compile(atoms).unwrap();
+        let (_, result, _) = compiler.compile(atoms).unwrap();
         assert_eq!(
             result,
             vec![
@@ -716,7 +859,7 @@ mod compiler {
             )
             .unwrap();
         let compiler = Compiler::default();
-        let (_, result) = compiler.compile(atoms).unwrap();
+        let (_, result, _) = compiler.compile(atoms).unwrap();
         assert_eq!(
             result,
             vec![
@@ -775,7 +918,7 @@ mod compiler {
             )
             .unwrap();
         let compiler = Compiler::default();
-        let (_, result) = compiler.compile(atoms).unwrap();
+        let (_, result, _) = compiler.compile(atoms).unwrap();
         assert_eq!(
             result,
             vec![
@@ -815,7 +958,7 @@ mod compiler {
             )
             .unwrap();
         let compiler = Compiler::default();
-        let (_, result) = compiler.compile(atoms).unwrap();
+        let (_, result, _) = compiler.compile(atoms).unwrap();
         assert_eq!(
             result,
             vec![
@@ -862,7 +1005,7 @@ mod compiler {
             .unwrap();
         let mut compiler = Compiler::default();
         compiler.lift_operators().unwrap();
-        let (_, result) = compiler.compile(atoms).unwrap();
+        let (_, result, _) = compiler.compile(atoms).unwrap();
         assert_eq!(
             result,
             vec![
@@ -921,7 +1064,7 @@ mod compiler {
             .unwrap();
         let mut compiler = Compiler::default();
         compiler.lift_operators().unwrap();
-        let (_, result) = compiler.compile(atoms).unwrap();
+        let (_, result, _) = compiler.compile(atoms).unwrap();
         assert_eq!(
             result,
             vec![
@@ -1011,7 +1154,7 @@ mod compiler {
             .unwrap();
         let mut compiler = Compiler::default();
         compiler.lift_operators().unwrap();
-        let (_, result) = compiler.compile(atoms).unwrap();
+        let (_, result, _) = compiler.compile(atoms).unwrap();
         assert_eq!(
             result,
             vec![
@@ -1083,7 +1226,7 @@ mod compiler {
             .unwrap();
         let mut compiler = Compiler::default();
         compiler.lift_operators().unwrap();
-        let (_, result) = compiler.compile(atoms).unwrap();
+        let (_, result, _) = compiler.compile(atoms).unwrap();
         println!("{result:?}");
         assert_eq!(
             result,
@@ -1121,4 +1264,1109 @@ mod compiler {
             ]
         );
     }
+
+    #[test]
+    fn debug_info() {
+        let parser = ListsParser::new();
+        let source =
+            "(def twice (f x)\n  (f (f x)))\n(def main ()\n  (twice (\\ (v) (* v 2)) 3))\n";
+        let atoms = parser.parse(source).unwrap();
+        let ((syms, ops, consts), debug) = Compiler::default()
+            .with_source("test.l", source)
+            .compile_with_debug_info(atoms)
+            .unwrap();
+        //
+        // The calls map to the application forms.
+        //
+        let location = |name: &str, nth: usize| {
+            let (_, start, _) = syms.iter().find(|(k, _, _)| k.as_ref() == name).unwrap();
+            let (pc, _) = ops
+                .iter()
+                .enumerate()
+                .skip(*start)
+                .filter(|(_, v)| matches!(v, OpCode::Call(_)))
+                .nth(nth)
+                .unwrap();
+            let loc = debug.location(pc).unwrap();
+            (debug.file(loc.file).unwrap(), loc.line, loc.column)
+        };
+        assert_eq!(location("twice", 0), ("test.l", 2, 6));
+        assert_eq!(location("twice", 1), ("test.l", 2, 3));
+        assert_eq!(location("main", 0), ("test.l", 4, 3));
+        //
+        // The lambdas know where they come from.
+        //
+        let (name, _, _) = syms
+            .iter()
+            .find(|(k, _, _)| k.starts_with("LAMBDA"))
+            .unwrap();
+        let lambda = debug.lambda(name).unwrap();
+        assert_eq!(lambda.parent.as_ref(), "main");
+        assert_eq!(lambda.text.as_ref(), "(\\ (v) (* v 2))");
+        //
+        // The debug information survives the serialization.
+        //
+        let binary = Binary::new(syms, ops, consts).with_debug_info(debug);
+        let mut bytes = Vec::new();
+        binary.encode(&mut bytes).unwrap();
+        assert_eq!(Binary::decode(&mut bytes.as_slice()).unwrap(), binary);
+    }
+
+    #[test]
+    fn binary_container() {
+        let parser = ListsParser::new();
+        let atoms = parser.parse("(def main () (+ 1 2))").unwrap();
+        let (syms, ops, consts) = Compiler::default().compile(atoms).unwrap();
+        let binary = Binary::new(syms, ops, consts);
+        let mut bytes = Vec::new();
+        binary.encode(&mut bytes).unwrap();
+        assert_eq!(Binary::decode(&mut bytes.as_slice()).unwrap(), binary);
+        //
+        // Reject files that are not binaries.
+        //
+        let result = Binary::decode(&mut "(def main () 1)".as_bytes());
+        assert!(matches!(result, Err(Error::InvalidBinary(_))));
+        //
+        // Reject binaries with a different format version.
+        //
+        let mut other = bytes.clone();
+        other[4] += 1;
+        let result = Binary::decode(&mut other.as_slice());
+        let expected = FORMAT_VERSION + 1;
+        assert!(matches!(result, Err(Error::IncompatibleBinary(v)) if v == expected));
+        //
+        // Reject corrupted binaries.
+        //
+        let mut other = bytes.clone();
+        *other.last_mut().unwrap() ^= 0xff;
+        let result = Binary::decode(&mut other.as_slice());
+        assert!(matches!(result, Err(Error::CorruptedBinary)));
+        //
+        // Reject inconsistent constant pools, loads, branches and funcalls.
+        //
+        let inconsistent = [
+            (vec![OpCode::Ldc(0)], vec![Constant::Pair(0, 1)]),
+            (
+                vec![OpCode::Ldc(1)],
+                vec![Constant::Immediate(Immediate::Nil)],
+            ),
+            (vec![OpCode::Psh(Immediate::Symbol(0))], vec![]),
+            (vec![OpCode::Br(2)], vec![]),
+            (vec![OpCode::Brn(-1)], vec![]),
+            (vec![OpCode::Get(0)], vec![]),
+            (
+                vec![OpCode::Psh(Immediate::Funcall(1, Arity::None))],
+                vec![],
+            ),
+            (vec![OpCode::Psh(Immediate::Funcall(0, Arity::All))], vec![]),
+            (
+                vec![OpCode::Ret],
+                vec![Constant::Immediate(Immediate::Funcall(0, Arity::All))],
+            ),
+            (vec![], vec![]),
+        ];
+        inconsistent.into_iter().for_each(|(ops, consts)| {
+            let mut bytes = Vec::new();
+            let binary = Binary::new(binary.symbols.clone(), ops, consts);
+            binary.encode(&mut bytes).unwrap();
+            let result = Binary::decode(&mut bytes.as_slice());
+            assert!(matches!(result, Err(Error::InvalidBinary(_))));
+        });
+    }
+}
+
+//
+// Virtual machine.
+//
+
+mod vm {
+    use std::{collections::BTreeSet, time::Duration};
+
+    use crate::{
+        compiler::Compiler,
+        error::Error,
+        grammar::ListsParser,
+        opcodes::{Arity, Constant, Immediate, OpCode},
+        stack::Kind,
+        syscalls::{Class, Policy, Registry},
+        vm::{Overflow, Status, VirtualMachine},
+    };
+
+    #[test]
+    fn add_type_mismatch() {
+        let parser = ListsParser::new();
+        let atoms = parser.parse("(def main () (+ 1 'a))").unwrap();
+        let compiler = Compiler::default();
+        let (syms, ops, consts) = compiler.compile(atoms).unwrap();
+        let mut vm = VirtualMachine::new(32, false);
+        let result = vm.run(syms, ops, consts);
+        assert!(matches!(
+            result,
+            Err(Error::TypeMismatch(
+                2,
+                OpCode::Add,
+                Kind::Number,
+                Kind::Symbol
+            ))
+        ));
+    }
+
+    #[test]
+    fn division_by_zero() {
+        let parser = ListsParser::new();
+        let atoms = parser.parse("(def main () (/ 1 0))").unwrap();
+        let compiler = Compiler::default();
+        let (syms, ops, consts) = compiler.compile(atoms).unwrap();
+        let mut vm = VirtualMachine::new(32, false);
+        let result = vm.run(syms, ops, consts);
+        assert!(matches!(result, Err(Error::DivisionByZero(2))));
+    }
+
+    #[test]
+    fn mixed_numeric_consistency() {
+        let parser = ListsParser::new();
+        let run = |text: &str| {
+            let atoms = parser.parse(&format!("(def main () {text})")).unwrap();
+            let (syms, ops, consts) = Compiler::default().compile(atoms).unwrap();
+            let mut vm = VirtualMachine::new(32, false);
+            vm.run(syms, ops, consts)
+                .map(|v| vm.display(&v).to_string())
+        };
+        //
+        // Equality agrees with the ordering operators.
+        //
+        assert_eq!(run("(= 1 1.0)").unwrap(), "T");
+        assert_eq!(run("(<> 1 1.0)").unwrap(), "nil");
+        assert_eq!(run("(<= 1 1.0)").unwrap(), "T");
+        assert_eq!(run("(= 1 1.5)").unwrap(), "nil");
+        assert_eq!(run("(= '(1 2) '(1.0 2))").unwrap(), "T");
+        assert_eq!(run("(= 99999999999999999999 1e20)").unwrap(), "T");
+        //
+        // Float divisors of zero are rejected like integer ones.
+        //
+        assert!(matches!(run("(% 5 0.0)"), Err(Error::DivisionByZero(_))));
+        assert!(matches!(run("(/ 5 -0.0)"), Err(Error::DivisionByZero(_))));
+        assert!(matches!(run("(% 5 0)"), Err(Error::DivisionByZero(_))));
+        //
+        // Negation keeps the sign of zero.
+        //
+        assert_eq!(run("(neg 0.0)").unwrap(), "-0.0");
+        assert_eq!(run("(neg -0.0)").unwrap(), "0.0");
+        assert_eq!(run("(neg 2)").unwrap(), "-2");
+    }
+
+    #[test]
+    fn arithmetic_overflow() {
+        let parser = ListsParser::new();
+        let atoms = parser
+            .parse("(def main () (+ 9223372036854775807 1))")
+            .unwrap();
+        let compiler = Compiler::default();
+        let (syms, ops, consts) = compiler.compile(atoms).unwrap();
+        let mut vm = VirtualMachine::new(32, false);
+        let result = vm.run(syms, ops, consts);
+        assert!(matches!(result, Err(Error::ArithmeticOverflow(2))));
+    }
+
+    #[test]
+    fn arithmetic_overflow_with_promotion() {
+        let parser = ListsParser::new();
+        let atoms = parser
+            .parse("(def main () (- (* 9223372036854775807 4) 1))")
+            .unwrap();
+        let compiler = Compiler::default();
+        let (syms, ops, consts) = compiler.compile(atoms).unwrap();
+        let mut vm = VirtualMachine::new(32, false).with_overflow(Overflow::Promote);
+        assert!(vm.run(syms, ops, consts).is_ok());
+    }
+
+    #[test]
+    fn bignum_literal() {
+        let parser = ListsParser::new();
+        let atoms = parser
+            .parse("(def main () (- 99999999999999999999 1))")
+            .unwrap();
+        let compiler = Compiler::default();
+        let (syms, ops, consts) = compiler.compile(atoms).unwrap();
+        let mut vm = VirtualMachine::new(32, false);
+        let value = vm.run(syms, ops, consts).unwrap();
+        assert_eq!(vm.display(&value).to_string(), "99999999999999999998");
+    }
+
+    #[test]
+    fn shifts() {
+        let parser = ListsParser::new();
+        let run = |text: &str, overflow: Overflow| {
+            let atoms = parser.parse(&format!("(def main () {text})")).unwrap();
+            let (syms, ops, consts) = Compiler::default().compile(atoms).unwrap();
+            let mut vm = VirtualMachine::new(32, false).with_overflow(overflow);
+            vm.run(syms, ops, consts)
+                .map(|v| vm.display(&v).to_string())
+        };
+        //
+        // Boundary counts with the error policy.
+        //
+        let ok = |text: &str| run(text, Overflow::Error).unwrap();
+        assert_eq!(ok("(<< 1 62)"), "4611686018427387904");
+        assert_eq!(ok("(<< -1 63)"), "-9223372036854775808");
+        assert_eq!(ok("(>> -8 1)"), "-4");
+        assert_eq!(ok("(>> 1 63)"), "0");
+        assert_eq!(ok("(<< 99999999999999999999 1)"), "199999999999999999998");
+        assert_eq!(ok("(>> 99999999999999999999 63)"), "10");
+        assert!(matches!(
+            run("(<< 1 63)", Overflow::Error),
+            Err(Error::ArithmeticOverflow(_))
+        ));
+        assert!(matches!(
+            run("(<< 1 64)", Overflow::Error),
+            Err(Error::InvalidShift(_, 64))
+        ));
+        assert!(matches!(
+            run("(>> 1 -1)", Overflow::Error),
+            Err(Error::InvalidShift(_, -1))
+        ));
+        //
+        // Boundary counts with the promotion policy.
+        //
+        let ok = |text: &str| run(text, Overflow::Promote).unwrap();
+        assert_eq!(ok("(<< 1 62)"), "4611686018427387904");
+        assert_eq!(ok("(<< 1 63)"), "9223372036854775808");
+        assert_eq!(ok("(<< 3 63)"), "27670116110564327424");
+        assert!(matches!(
+            run("(<< 1 64)", Overflow::Promote),
+            Err(Error::InvalidShift(_, 64))
+        ));
+    }
+
+    #[test]
+    fn bitwise_float_type_mismatch() {
+        let parser = ListsParser::new();
+        let atoms = parser.parse("(def main () (& 1.0 3))").unwrap();
+        let compiler = Compiler::default();
+        let (syms, ops, consts) = compiler.compile(atoms).unwrap();
+        let mut vm = VirtualMachine::new(32, false);
+        let result = vm.run(syms, ops, consts);
+        assert!(matches!(
+            result,
+            Err(Error::TypeMismatch(
+                2,
+                OpCode::Band,
+                Kind::Number,
+                Kind::Float
+            ))
+        ));
+    }
+
+    #[test]
+    fn call_type_mismatch() {
+        let parser = ListsParser::new();
+        let atoms = parser.parse("(def main () (1 2))").unwrap();
+        let compiler = Compiler::default();
+        let (syms, ops, consts) = compiler.compile(atoms).unwrap();
+        let mut vm = VirtualMachine::new(32, false);
+        let result = vm.run(syms, ops, consts);
+        assert!(matches!(
+            result,
+            Err(Error::TypeMismatch(
+                2,
+                OpCode::Call(1),
+                Kind::Callable,
+                Kind::Number
+            ))
+        ));
+    }
+
+    #[test]
+    fn list_of_return_link() {
+        let syms = vec![("main".into(), 0, Arity::None)];
+        let ops = vec![OpCode::Lst(1), OpCode::Ret];
+        let mut vm = VirtualMachine::new(32, false);
+        let result = vm.run(syms, ops, Vec::new());
+        assert!(matches!(
+            result,
+            Err(Error::TypeMismatch(
+                0,
+                OpCode::Lst(1),
+                Kind::Value,
+                Kind::Link
+            ))
+        ));
+    }
+
+    #[test]
+    fn garbage_collection() {
+        let parser = ListsParser::new();
+        let atoms = parser
+            .parse(
+                r#"
+                (def loop (N)
+                    (if (> N 0)
+                        (prog (cons N N) (loop (- N 1)))
+                        N))
+                (def main () (loop 1000))
+                "#,
+            )
+            .unwrap();
+        let compiler = Compiler::default();
+        let (syms, ops, consts) = compiler.compile(atoms).unwrap();
+        let mut vm = VirtualMachine::new(4096, false).with_gc_threshold(64);
+        assert!(vm.run(syms, ops, consts).is_ok());
+        let stats = vm.stats();
+        assert_eq!(stats.allocations, 3000);
+        assert!(stats.collections > 0);
+        assert!(stats.freed > 0);
+        assert_eq!(stats.allocations, stats.freed + stats.live);
+    }
+
+    #[test]
+    fn catch_unwinds_nested_calls() {
+        let parser = ListsParser::new();
+        let atoms = parser
+            .parse(
+                r#"
+                (def fail (N) (if (> N 3) (throw N) (fail (+ N 1))))
+                (def main ()
+                    (let ((a . 2))
+                        (let ((r . (catch (\ (e) (* e 10)) (+ 1 (fail 0)))))
+                            (cons r a))))
+                "#,
+            )
+            .unwrap();
+        let compiler = Compiler::default();
+        let (syms, ops, consts) = compiler.compile(atoms).unwrap();
+        let mut vm = VirtualMachine::new(32, false);
+        let value = vm.run(syms, ops, consts).unwrap();
+        //
+        // The handler result is returned and the binding made before the
+        // catch is still found at its slot, so the stack was unwound back to
+        // its depth at the catch.
+        //
+        assert_eq!(vm.display(&value).to_string(), "(40 . 2)");
+        assert!(vm.stack().is_empty());
+    }
+
+    #[test]
+    fn uncaught_exception() {
+        let parser = ListsParser::new();
+        let atoms = parser
+            .parse(r#"(def main () (catch (\ (e) (throw e)) (throw '(oops . "bad"))))"#)
+            .unwrap();
+        let compiler = Compiler::default();
+        let (syms, ops, consts) = compiler.compile(atoms).unwrap();
+        let mut vm = VirtualMachine::new(32, false);
+        let result = vm.run(syms, ops, consts);
+        assert!(
+            matches!(result, Err(Error::UncaughtException(_, v)) if v.as_ref() == r#"(oops . "bad")"#)
+        );
+    }
+
+    #[test]
+    fn eval_and_display() {
+        let parser = ListsParser::new();
+        let atoms = parser
+            .parse("(def main () (cons 1 (cons 2.5 (cons 'a 3))))")
+            .unwrap();
+        let compiler = Compiler::default();
+        let (syms, ops, consts) = compiler.compile(atoms).unwrap();
+        let mut vm = VirtualMachine::new(32, false);
+        let value = vm.eval(&syms, &ops, &consts).unwrap();
+        assert_eq!(vm.display(&value).to_string(), "(1 2.5 a . 3)");
+    }
+
+    #[test]
+    fn display_round_trip() {
+        let parser = ListsParser::new();
+        let source = r#"(1 -2.5 a "hi\n" ^b ^\t (T . nil) (1 . 2))"#;
+        let expected = r#"(1 -2.5 a "hi\n" ^b ^\t (T) (1 . 2))"#;
+        let mut printed = Vec::new();
+        for text in [source, expected] {
+            let atoms = parser.parse(&format!("(def main () '{text})")).unwrap();
+            let compiler = Compiler::default();
+            let (syms, ops, consts) = compiler.compile(atoms).unwrap();
+            let mut vm = VirtualMachine::new(32, false);
+            let value = vm.run(syms, ops, consts).unwrap();
+            printed.push(vm.display(&value).to_string());
+        }
+        assert_eq!(printed, vec![expected, expected]);
+    }
+
+    #[test]
+    fn display_closure() {
+        let parser = ListsParser::new();
+        let atoms = parser
+            .parse("(def add (a b c) (+ a b c)) (def main () (add 1))")
+            .unwrap();
+        let compiler = Compiler::default();
+        let (syms, ops, consts) = compiler.compile(atoms).unwrap();
+        let addr = syms.iter().find(|v| v.0.as_ref() == "add").unwrap().1;
+        let mut vm = VirtualMachine::new(32, false);
+        let value = vm.run(syms, ops, consts).unwrap();
+        let expected = format!("#<closure {addr:04}/2>");
+        assert_eq!(vm.display(&value).to_string(), expected);
+    }
+
+    #[test]
+    fn exit_status() {
+        let parser = ListsParser::new();
+        let statuses: Vec<_> = ["42", "nil", "T", "'(1 2)", "256", "-1", "4294967297"]
+            .into_iter()
+            .map(|v| {
+                let atoms = parser.parse(&format!("(def main () {v})")).unwrap();
+                let compiler = Compiler::default();
+                let (syms, ops, consts) = compiler.compile(atoms).unwrap();
+                let mut vm = VirtualMachine::new(32, false);
+                let value = vm.run(syms, ops, consts).unwrap();
+                VirtualMachine::status(&value)
+            })
+            .collect();
+        assert_eq!(statuses, vec![42, 1, 0, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn exit_syscall() {
+        let parser = ListsParser::new();
+        let statuses: Vec<_> = ["3", "256", "T"]
+            .into_iter()
+            .map(|v| {
+                let source = format!("(def main () (prog (syscall EXIT {v}) 42))");
+                let atoms = parser.parse(&source).unwrap();
+                let (syms, ops, consts) = Compiler::default().compile(atoms).unwrap();
+                let mut vm = VirtualMachine::new(32, false);
+                match vm.run(syms, ops, consts) {
+                    Err(Error::Exit(_, status)) => status,
+                    _ => panic!("expected the program to exit"),
+                }
+            })
+            .collect();
+        assert_eq!(statuses, vec![3, 1, 0]);
+    }
+
+    #[test]
+    fn main_arguments_and_environment() {
+        let parser = ListsParser::new();
+        let atoms = parser
+            .parse(r#"(def main (ARGS) (cons (syscall GETENV "SL_UNDEFINED_VAR") ARGS))"#)
+            .unwrap();
+        let compiler = Compiler::default();
+        let (syms, ops, consts) = compiler.compile(atoms).unwrap();
+        let arguments = vec!["a".into(), "b c".into()];
+        let mut vm = VirtualMachine::new(32, false).with_arguments(arguments);
+        let value = vm.run(syms, ops, consts).unwrap();
+        assert_eq!(vm.display(&value).to_string(), r#"(nil "a" "b c")"#);
+    }
+
+    #[test]
+    fn file_syscalls() {
+        let dir = std::env::temp_dir().join(format!("sl-fs-{}", std::process::id()));
+        let path = dir.join("a.txt");
+        let source = format!(
+            r#"
+            (def list A A)
+            (def main ()
+                (let ((a . (syscall MKDIR "{dir}" 493))
+                      (fd . (syscall OPEN "{path}" 7 420))
+                      (b . (syscall WRITE fd "héllo"))
+                      (c . (syscall LSEEK fd 1 0))
+                      (d . (syscall READ fd 4611686018427387904))
+                      (e . (syscall CLOSE fd))
+                      (f . (car (syscall STAT "{path}")))
+                      (g . (syscall READDIR "{dir}"))
+                      (h . (syscall UNLINK "{path}"))
+                      (i . (syscall STAT "{path}")))
+                    (list a b c d e f g h i)))
+            "#,
+            dir = dir.display(),
+            path = path.display(),
+        );
+        let parser = ListsParser::new();
+        let atoms = parser.parse(&source).unwrap();
+        let compiler = Compiler::default();
+        let (syms, ops, consts) = compiler.compile(atoms).unwrap();
+        let mut vm = VirtualMachine::new(32, false);
+        let value = vm.run(syms, ops, consts).unwrap();
+        let _ = std::fs::remove_dir(&dir);
+        let expected = format!(r#"(0 6 1 "éllo" 0 6 ("a.txt") 0 {})"#, -libc::ENOENT);
+        assert_eq!(vm.display(&value).to_string(), expected);
+    }
+
+    #[test]
+    fn process_syscalls() {
+        let parser = ListsParser::new();
+        let atoms = parser
+            .parse(
+                r#"
+                (def list A A)
+                (def main ()
+                    (let ((fds . (syscall PIPE))
+                          (a . (syscall WRITE (cdr fds) "ping"))
+                          (b . (syscall READ (car fds) 16))
+                          (c . (= (syscall GETPID) (syscall GETPID)))
+                          (d . (syscall KILL (syscall GETPID) 0)))
+                        (syscall CLOSE (car fds))
+                        (syscall CLOSE (cdr fds))
+                        (list a b c d)))
+                "#,
+            )
+            .unwrap();
+        let compiler = Compiler::default();
+        let (syms, ops, consts) = compiler.compile(atoms).unwrap();
+        let mut vm = VirtualMachine::new(32, false);
+        let value = vm.run(syms, ops, consts).unwrap();
+        assert_eq!(vm.display(&value).to_string(), r#"(4 "ping" T 0)"#);
+    }
+
+    #[test]
+    fn host_functions() {
+        let mut registry = Registry::default();
+        let index = registry
+            .register("SCALE", Class::Host, 2, |_, values| {
+                match (values[1].as_immediate(), values[0].as_immediate()) {
+                    (Immediate::Number(a), Immediate::Number(b)) => Immediate::Number(a * b).into(),
+                    _ => Immediate::Nil.into(),
+                }
+            })
+            .unwrap();
+        assert_eq!(index, 19);
+        assert!(
+            registry
+                .register("WRITE", Class::Fs, 2, |_, _| Immediate::Nil.into())
+                .is_err()
+        );
+        let parser = ListsParser::new();
+        let atoms = parser
+            .parse("(def main () (+ 1 (syscall SCALE 6 7)))")
+            .unwrap();
+        let compiler = Compiler::default().with_registry(&registry);
+        let (syms, ops, consts) = compiler.compile(atoms).unwrap();
+        let unknown = (syms.clone(), ops.clone(), consts.clone());
+        let mut vm = VirtualMachine::new(32, false).with_registry(registry);
+        let value = vm.run(syms, ops, consts).unwrap();
+        assert_eq!(vm.display(&value).to_string(), "43");
+        let atoms = parser.parse("(def main () (syscall SCALE 6 7))").unwrap();
+        let result = Compiler::default().compile(atoms);
+        assert!(
+            matches!(result, Err(Error::Located(_, e)) if matches!(*e, Error::InvalidSystemCall(_)))
+        );
+        //
+        // Host functions missing at runtime are rejected.
+        //
+        let mut vm = VirtualMachine::new(32, false);
+        let result = vm.run(unknown.0, unknown.1, unknown.2);
+        assert!(matches!(result, Err(Error::UnknownSystemCall(_, 19))));
+    }
+
+    #[test]
+    fn syscall_policy() {
+        let parser = ListsParser::new();
+        let source = "(def main () (syscall GETENV \"HOME\"))";
+        //
+        // Denied system calls are rejected at compile time.
+        //
+        let policy = Policy::deny_all().allow("fs").allow("GETPID");
+        let registry = Registry::default().with_policy(policy.clone());
+        let atoms = parser.parse(source).unwrap();
+        let result = Compiler::default().with_registry(&registry).compile(atoms);
+        assert!(
+            matches!(result, Err(Error::Located(_, e)) if matches!(*e, Error::InvalidSystemCall(_)))
+        );
+        //
+        // And at runtime for code compiled without the policy.
+        //
+        let atoms = parser.parse(source).unwrap();
+        let (syms, ops, consts) = Compiler::default().compile(atoms).unwrap();
+        let mut vm = VirtualMachine::new(32, false).with_policy(policy);
+        let result = vm.run(syms, ops, consts);
+        assert!(matches!(result, Err(Error::SystemCallDenied(_, v)) if v.as_ref() == "GETENV"));
+        //
+        // Rules on names take precedence over rules on classes.
+        //
+        let policy = Policy::allow_all().deny("env").allow("GETENV");
+        assert!(policy.permits("GETENV", Class::Env));
+        assert!(!policy.permits("ENV", Class::Env));
+        assert!(policy.permits("WRITE", Class::Fs));
+        //
+        // Denying the file system also denies the file descriptors.
+        //
+        let registry = Registry::default().with_policy(Policy::allow_all().deny("fs"));
+        let signatures = registry.signatures();
+        assert!(
+            ["OPEN", "READ", "WRITE", "CLOSE", "LSEEK"]
+                .iter()
+                .all(|v| signatures.get(v).is_err())
+        );
+    }
+
+    #[test]
+    fn budgets() {
+        let parser = ListsParser::new();
+        let compile = |source: &str| {
+            let atoms = parser.parse(source).unwrap();
+            Compiler::default().compile(atoms).unwrap()
+        };
+        let looping = "(def loop () (loop)) (def main () (loop))";
+        let recursing = "(def f (n) (+ 1 (f n))) (def main () (f 1))";
+        //
+        // Fuel.
+        //
+        let (syms, ops, consts) = compile(looping);
+        let mut vm = VirtualMachine::new(32, false).with_fuel(1000);
+        assert!(matches!(
+            vm.run(syms, ops, consts),
+            Err(Error::FuelExhausted(_))
+        ));
+        //
+        // Deadline.
+        //
+        let (syms, ops, consts) = compile(looping);
+        let mut vm = VirtualMachine::new(32, false).with_timeout(Duration::from_millis(10));
+        assert!(matches!(
+            vm.run(syms, ops, consts),
+            Err(Error::DeadlineExceeded(_))
+        ));
+        //
+        // Stack depth.
+        //
+        let (syms, ops, consts) = compile(recursing);
+        let mut vm = VirtualMachine::new(32, false).with_max_depth(256);
+        assert!(matches!(
+            vm.run(syms, ops, consts),
+            Err(Error::StackOverflow(_))
+        ));
+        //
+        // The budget is reset on every run.
+        //
+        let (syms, ops, consts) = compile("(def main () (+ 1 2))");
+        let mut vm = VirtualMachine::new(32, false).with_fuel(16);
+        assert!(vm.eval(&syms, &ops, &consts).is_ok());
+        assert!(vm.eval(&syms, &ops, &consts).is_ok());
+    }
+
+    #[test]
+    fn step_and_resume() {
+        let parser = ListsParser::new();
+        let source = r#"
+            (def count (n) (if (< n 3) (prog (yield n) (count (+ n 1))) n))
+            (def main () (count 0))
+        "#;
+        let atoms = parser.parse(source).unwrap();
+        let (syms, ops, consts) = Compiler::default().compile(atoms).unwrap();
+        let mut vm = VirtualMachine::new(32, false);
+        //
+        // Running to completion ignores the yields.
+        //
+        let value = vm.run(syms.clone(), ops.clone(), consts.clone()).unwrap();
+        assert_eq!(vm.display(&value).to_string(), "3");
+        //
+        // Stepping preserves the state across calls.
+        //
+        vm.load(syms, ops, consts).unwrap();
+        assert_eq!(vm.step(1).unwrap(), Status::Running);
+        let mut yields = Vec::new();
+        let result = loop {
+            match vm.resume().unwrap() {
+                Status::Yielded(v) => yields.push(vm.display(&v).to_string()),
+                Status::Finished(v) => break vm.display(&v).to_string(),
+                Status::Running => unreachable!(),
+            }
+        };
+        assert_eq!(yields, vec!["0", "1", "2"]);
+        assert_eq!(result, "3");
+        assert!(matches!(vm.resume(), Err(Error::NotRunning)));
+    }
+
+    #[test]
+    fn constant_pool() {
+        let parser = ListsParser::new();
+        let source = r#"
+            (def count (n) (if (= n 0) '("done" . 0) (prog (cons n n) (count (- n 1)))))
+            (def main () (count 1000))
+        "#;
+        let atoms = parser.parse(source).unwrap();
+        let (syms, ops, consts) = Compiler::default().compile(atoms).unwrap();
+        assert!(ops.iter().any(|v| matches!(v, OpCode::Ldc(_))));
+        assert_eq!(ops.iter().filter(|v| matches!(v, OpCode::Cons)).count(), 1);
+        //
+        // The constants survive the collections.
+        //
+        let mut vm = VirtualMachine::new(32, false).with_gc_threshold(16);
+        let value = vm.run(syms, ops, consts).unwrap();
+        assert_eq!(vm.display(&value).to_string(), "(\"done\" . 0)");
+        assert!(vm.stats().collections > 0);
+    }
+
+    #[test]
+    fn result_survives_next_run() {
+        let parser = ListsParser::new();
+        let mut vm = VirtualMachine::new(32, false).with_gc_threshold(16);
+        let atoms = parser.parse("(def main () (cons 1 2))").unwrap();
+        let (syms, ops, consts) = Compiler::default().compile(atoms).unwrap();
+        let value = vm.run(syms, ops, consts).unwrap();
+        //
+        // The result stays valid while another program collects the garbage.
+        //
+        let source = r#"
+            (def loop (N) (if (> N 0) (prog (cons N N) (loop (- N 1))) N))
+            (def main () (loop 100))
+        "#;
+        let atoms = parser.parse(source).unwrap();
+        let (syms, ops, consts) = Compiler::default().compile(atoms).unwrap();
+        vm.load(syms, ops, consts).unwrap();
+        assert_eq!(vm.step(1000).unwrap(), Status::Running);
+        assert!(vm.stats().collections > 0);
+        assert_eq!(vm.display(&value).to_string(), "(1 . 2)");
+    }
+
+    #[test]
+    fn constant_pool_reuse_and_bounds() {
+        let parser = ListsParser::new();
+        let atoms = parser.parse("(def main () '(1 2 3))").unwrap();
+        let (syms, ops, consts) = Compiler::default().compile(atoms).unwrap();
+        //
+        // The pool is built once, evaluations only allocate their results.
+        //
+        let mut vm = VirtualMachine::new(32, false);
+        vm.eval(&syms, &ops, &consts).unwrap();
+        let allocations = vm.stats().allocations;
+        vm.eval(&syms, &ops, &consts).unwrap();
+        assert_eq!(vm.stats().allocations, allocations);
+        //
+        // Out-of-range constants are rejected.
+        //
+        let syms = vec![("main".into(), 0, Arity::None)];
+        let ops = vec![OpCode::Ldc(3), OpCode::Ret];
+        let result = vm.run(syms.clone(), ops, Vec::new());
+        assert!(matches!(result, Err(Error::InvalidConstant(3))));
+        let consts = vec![Constant::Pair(1, 1), Constant::Immediate(Immediate::Nil)];
+        let ops = vec![OpCode::Ldc(0), OpCode::Ret];
+        let result = vm.run(syms, ops, consts);
+        assert!(matches!(result, Err(Error::InvalidConstant(1))));
+    }
+
+    #[test]
+    fn native_strings() {
+        let parser = ListsParser::new();
+        let cases = [
+            (r#"(len "hello")"#, "5"),
+            (r#"(len nil)"#, "0"),
+            (r#"(cat "foo" (str 42))"#, "\"foo42\""),
+            (r#"(substr "hello world" 6 100)"#, "\"world\""),
+            (r#"(substr "hello" 3 1)"#, "nil"),
+            (r#"(cmp "abc" "abd")"#, "-1"),
+            (r#"(cmp "abc" (cons ^a "bc"))"#, "0"),
+            (r#"(find "hello" "ll")"#, "2"),
+            (r#"(find "hello" "z")"#, "nil"),
+            (r#"(car "abc")"#, "^a"),
+            (r#"(cdr "abc")"#, "\"bc\""),
+            (r#"(cdr "a")"#, "nil"),
+            (r#"(lst? "abc")"#, "T"),
+            (r#"(= "abc" (cons ^a (cons ^b (cons ^c nil))))"#, "T"),
+            (r#"(= "abc" "abd")"#, "nil"),
+            (r#"(cons ^a "bc")"#, "\"abc\""),
+            (r#"(len "naïve")"#, "5"),
+            (r#"(car "élan")"#, "^é"),
+            (r#"(cdr "élan")"#, "\"lan\""),
+            (r#"(substr "naïve café" 6 10)"#, "\"café\""),
+            (r#"(find "naïve café" "café")"#, "6"),
+            (r#"(= "é\u{1F600}" (cons ^é (cons ^\u{1F600} nil)))"#, "T"),
+            (r#"(str ^\u{7})"#, "\"\\u{7}\""),
+        ];
+        cases.into_iter().for_each(|(text, expected)| {
+            let atoms = parser.parse(&format!("(def main () {text})")).unwrap();
+            let (syms, ops, consts) = Compiler::default().compile(atoms).unwrap();
+            let mut vm = VirtualMachine::new(32, false);
+            let value = vm.run(syms, ops, consts).unwrap();
+            assert_eq!(vm.display(&value).to_string(), expected, "{text}");
+        });
+        //
+        // String operations reject other values.
+        //
+        let atoms = parser.parse("(def main () (len 1))").unwrap();
+        let (syms, ops, consts) = Compiler::default().compile(atoms).unwrap();
+        let mut vm = VirtualMachine::new(32, false);
+        assert!(matches!(
+            vm.run(syms, ops, consts),
+            Err(Error::TypeMismatch(
+                _,
+                OpCode::Len,
+                Kind::String,
+                Kind::Number
+            ))
+        ));
+    }
+
+    #[test]
+    fn lifted_string_operators() {
+        let parser = ListsParser::new();
+        let source = r#"
+            (def map (fun lst)
+                (if (nil? lst)
+                    nil
+                    (cons (fun (car lst)) (map fun (cdr lst)))))
+            (def main ()
+                (let ((lens . (map len '("a" "bc" "naïve")))
+                      (strs . (map str '(1 ^b sym)))
+                      (cats . (map (cat "x") '("y" "z"))))
+                    (cons lens (cons strs (cons cats nil)))))
+        "#;
+        let atoms = parser.parse(source).unwrap();
+        let mut compiler = Compiler::default();
+        compiler.lift_operators().unwrap();
+        let (syms, ops, consts) = compiler.compile(atoms).unwrap();
+        let mut vm = VirtualMachine::new(32, false);
+        let value = vm.run(syms, ops, consts).unwrap();
+        let expected = r#"((1 2 5) ("1" "b" "sym") ("xy" "xz"))"#;
+        assert_eq!(vm.display(&value).to_string(), expected);
+    }
+
+    #[test]
+    fn interned_symbols() {
+        let parser = ListsParser::new();
+        let source = r#"
+            (def list A A)
+            (def main ()
+                (list (= 'a_very_long_symbol_name 'a_very_long_symbol_name)
+                      (= 'a_very_long_symbol_name_1 'a_very_long_symbol_name_2)
+                      (str 'a_very_long_symbol_name)
+                      '(a_very_long_symbol_name . b)))
+        "#;
+        let atoms = parser.parse(source).unwrap();
+        let (syms, ops, consts) = Compiler::default().compile(atoms).unwrap();
+        //
+        // Each symbol is stored once in the constant pool.
+        //
+        let count = consts
+            .iter()
+            .filter(|v| matches!(v, Constant::Symbol(v) if v.as_ref() == "a_very_long_symbol_name"))
+            .count();
+        assert_eq!(count, 1);
+        //
+        // Symbols keep their full name.
+        //
+        let mut vm = VirtualMachine::new(32, false);
+        let value = vm.run(syms, ops, consts).unwrap();
+        let expected = r#"(T nil "a_very_long_symbol_name" (a_very_long_symbol_name . b))"#;
+        assert_eq!(vm.display(&value).to_string(), expected);
+    }
+
+    #[test]
+    fn shared_constants() {
+        let parser = ListsParser::new();
+        let source = r#"
+            (def list A A)
+            (def main ()
+                (list '(1 2) '(1 2) '(0.0 -0.0)))
+        "#;
+        let atoms = parser.parse(source).unwrap();
+        let (syms, ops, consts) = Compiler::default().compile(atoms).unwrap();
+        //
+        // Identical cells are stored once, floats are told apart bitwise.
+        //
+        let nils = consts
+            .iter()
+            .filter(|v| matches!(v, Constant::Immediate(Immediate::Nil)))
+            .count();
+        assert_eq!(nils, 1);
+        assert_eq!(consts.len(), 9);
+        //
+        // The lists are unchanged.
+        //
+        let mut vm = VirtualMachine::new(32, false);
+        let value = vm.run(syms, ops, consts).unwrap();
+        assert_eq!(vm.display(&value).to_string(), "((1 2) (1 2) (0.0 -0.0))");
+    }
+
+    #[test]
+    fn let_in_argument() {
+        let parser = ListsParser::new();
+        let source = "(def f (a) (- a (let ((c . 1)) c))) (def main () (f 5))";
+        let atoms = parser.parse(source).unwrap();
+        let (syms, ops, consts) = Compiler::default().compile(atoms).unwrap();
+        let mut vm = VirtualMachine::new(32, false);
+        let value = vm.run(syms, ops, consts).unwrap();
+        assert_eq!(vm.display(&value).to_string(), "4");
+    }
+
+    #[test]
+    fn debug_info_locals() {
+        let parser = ListsParser::new();
+        let source = r#"
+            (def add (a b) (let ((c . (+ a b))) (* c 2)))
+            (def apply (f x) (f x))
+            (def main () (let ((k . 10)) (apply (\ (y) (+ y k)) (add 3 4))))
+        "#;
+        let atoms = parser.parse(source).unwrap();
+        let ((syms, ops, consts), debug) =
+            Compiler::default().compile_with_debug_info(atoms).unwrap();
+        assert_eq!(
+            ops.len(),
+            (0..ops.len()).filter_map(|v| debug.depth(v)).count()
+        );
+        //
+        // Resolve the locals at every step of the execution.
+        //
+        let expected = [
+            ("a", "3"),
+            ("b", "4"),
+            ("c", "7"),
+            ("k", "10"),
+            ("x", "14"),
+            ("y", "14"),
+        ];
+        let mut vm = VirtualMachine::new(32, false);
+        let mut seen = BTreeSet::new();
+        vm.load(syms, ops, consts).unwrap();
+        while let Some(pc) = vm.pc() {
+            let base = vm.stack().len() - debug.depth(pc).unwrap();
+            debug.locals(pc).iter().for_each(|(name, slot)| {
+                let value = vm.stack().iter().nth(base + slot).unwrap();
+                let value = vm.display(value).to_string();
+                match expected.iter().find(|(k, _)| *k == name.as_ref()) {
+                    Some((_, v)) => assert_eq!(&value, v, "{name} at {pc}"),
+                    None => assert!(value.starts_with("#<closure"), "{name} at {pc}"),
+                }
+                seen.insert(name.clone());
+            });
+            vm.step(1).unwrap();
+        }
+        assert_eq!(seen.len(), 7);
+    }
+
+    #[test]
+    fn extend_compilation_unit() {
+        let parser = ListsParser::new();
+        let mut compiler = Compiler::default();
+        let mut ops = Vec::new();
+        let mut vm = VirtualMachine::new(32, false);
+        //
+        // Compile the initial definitions.
+        //
+        let atoms = parser.parse("(def sq (x) (* x x))").unwrap();
+        compiler.extend(atoms, &mut ops).unwrap();
+        let prefix = ops.clone();
+        //
+        // Reject invalid definitions without altering the unit.
+        //
+        let atoms = parser.parse("(def bad () (missing 1))").unwrap();
+        assert!(compiler.extend(atoms, &mut ops).is_err());
+        assert_eq!(ops, prefix);
+        assert_eq!(compiler.symbols().len(), 1);
+        //
+        // Append a definition that calls the previous one.
+        //
+        let atoms = parser.parse("(def main () (+ (sq 7) 1))").unwrap();
+        compiler.extend(atoms, &mut ops).unwrap();
+        assert_eq!(&ops[..prefix.len()], prefix.as_slice());
+        //
+        // Invoke the new definition.
+        //
+        let value = vm
+            .eval(compiler.symbols(), &ops, compiler.constants())
+            .unwrap();
+        assert_eq!(vm.display(&value).to_string(), "50");
+    }
+}
+
+//
+// Diagnostics.
+//
+
+mod diagnostic {
+    use crate::{compiler::Compiler, diagnostic::Diagnostic, grammar::ListsParser};
+
+    #[test]
+    fn unresolved_symbol() {
+        let source = "(def main ()\n  (+ 1 (foo 2)))";
+        let parser = ListsParser::new();
+        let atoms = parser.parse(source).unwrap();
+        let compiler = Compiler::default();
+        let error = compiler.compile(atoms).unwrap_err();
+        let result = Diagnostic::new(&error, "main.l", source).to_string();
+        assert_eq!(
+            result,
+            [
+                "error: Unresolved symbol: foo",
+                " --> main.l:2:9",
+                "  |",
+                "2 |   (+ 1 (foo 2)))",
+                "  |         ^^^",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn parse_error() {
+        let source = "(def main ())\n)";
+        let parser = ListsParser::new();
+        let error = parser.parse(source).unwrap_err().into();
+        let result = Diagnostic::new(&error, "main.l", source).to_string();
+        assert!(result.contains(" --> main.l:2:1"));
+    }
+}
+
+//
+// REPL.
+//
+
+mod repl {
+    use crate::{atom::Span, error::Error, repl::Repl, vm::VirtualMachine};
+
+    #[test]
+    fn survives_oversized_literal() {
+        let mut vm = VirtualMachine::new(32, false);
+        let mut repl = Repl::new(vm.registry()).unwrap();
+        let mut out = Vec::new();
+        repl.eval(&mut vm, "(+ 99999999999999999999 1)", &mut out)
+            .unwrap();
+        assert!(repl.eval(&mut vm, "\"oops", &mut out).is_err());
+        repl.eval(&mut vm, "(+ 1 2)", &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "100000000000000000000\n3\n"
+        );
+    }
+
+    #[test]
+    fn survives_failed_line() {
+        let mut vm = VirtualMachine::new(32, false);
+        let mut repl = Repl::new(vm.registry()).unwrap();
+        let mut out = Vec::new();
+        let failed = "(def f (x) (if x ((\\ (y) (+ y 1)) x) (g '(a b))))";
+        assert!(repl.eval(&mut vm, failed, &mut out).is_err());
+        let failed = "((\\ (y) (if y (+ y 1) 0)) (unknown))";
+        assert!(repl.eval(&mut vm, failed, &mut out).is_err());
+        repl.eval(
+            &mut vm,
+            "(def f (x) (if x ((\\ (y) (+ y 1)) x) '(a b)))",
+            &mut out,
+        )
+        .unwrap();
+        repl.eval(&mut vm, "(f 2) (f nil)", &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "3\n(a b)\n");
+    }
+
+    #[test]
+    fn empty_input_and_locations() {
+        let mut vm = VirtualMachine::new(32, false);
+        let mut repl = Repl::new(vm.registry()).unwrap();
+        let mut out = Vec::new();
+        repl.eval(&mut vm, "", &mut out).unwrap();
+        repl.eval(&mut vm, "; a comment", &mut out).unwrap();
+        let result = repl.eval(&mut vm, "(+ 1 (foo))", &mut out);
+        assert!(matches!(result, Err(Error::Located(span, _)) if span == Span::new(6, 9)));
+        repl.eval(&mut vm, "42 (+ 1 2)", &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "42\n3\n");
+    }
+
+    #[test]
+    fn lone_string_body() {
+        let mut vm = VirtualMachine::new(32, false);
+        let mut repl = Repl::new(vm.registry()).unwrap();
+        let mut out = Vec::new();
+        repl.eval(&mut vm, "(def hi () \"hello\")", &mut out)
+            .unwrap();
+        repl.eval(&mut vm, "(hi)", &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "\"hello\"\n");
+    }
 }
@@ -1,14 +1,106 @@
-use std::rc::Rc;
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    time::{Duration, Instant},
+};
+
+use num_bigint::{BigInt, Sign};
+use num_traits::{FromPrimitive, ToPrimitive};
 
 use crate::{
     error::Error,
-    heap,
-    opcodes::{Arity, Immediate, OpCode},
-    stack::{Stack, Value},
-    syscalls,
+    heap::{self, Dump, Heap, Printer, Stats, Str},
+    opcodes::{Arity, Constant, Immediate, OpCode},
+    stack::{Kind, Stack, Value},
+    syscalls::{Policy, Registry},
 };
 
+//
+// Overflow policy.
+//
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Overflow {
+    #[default]
+    Error,
+    Promote,
+}
+
+//
+// Limits.
+//
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Limits {
+    fuel: Option<usize>,
+    depth: Option<usize>,
+    timeout: Option<Duration>,
+}
+
+//
+// Budget.
+//
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Budget {
+    fuel: Option<usize>,
+    deadline: Option<Instant>,
+    ticks: usize,
+}
+
+impl Budget {
+    fn new(limits: &Limits) -> Self {
+        Self {
+            fuel: limits.fuel,
+            deadline: limits.timeout.map(|v| Instant::now() + v),
+            ticks: 0,
+        }
+    }
+}
+
+//
+// Status.
+//
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    Running,
+    Finished(Value),
+    Yielded(Value),
+}
+
+//
+// Stop.
+//
+
+enum Stop {
+    Finished,
+    Paused(usize),
+    Yielded(usize),
+}
+
+//
+// Deadline check interval.
+//
+
+const DEADLINE_INTERVAL: usize = 256;
+
+//
+// Virtual machine.
+//
+
 pub struct VirtualMachine {
+    arguments: Vec<String>,
+    budget: Budget,
+    constants: Vec<Value>,
+    handlers: Vec<(usize, usize)>,
+    heap: Heap,
+    limits: Limits,
+    ops: Vec<OpCode>,
+    overflow: Overflow,
+    pc: Option<usize>,
+    registry: Registry,
+    result: Option<Value>,
     stack: Stack,
     trace: bool,
 }
@@ -16,33 +108,317 @@ pub struct VirtualMachine {
 impl VirtualMachine {
     pub fn new(capacity: usize, trace: bool) -> Self {
         Self {
+            arguments: Vec::new(),
+            budget: Budget::default(),
+            constants: Vec::new(),
+            handlers: Vec::new(),
+            heap: Heap::default(),
+            limits: Limits::default(),
+            ops: Vec::new(),
+            overflow: Overflow::default(),
+            pc: None,
+            registry: Registry::default(),
+            result: None,
             stack: Stack::new(capacity),
             trace,
         }
     }
 
+    pub fn with_arguments(mut self, arguments: Vec<String>) -> Self {
+        self.arguments = arguments;
+        self
+    }
+
+    pub fn with_fuel(mut self, fuel: usize) -> Self {
+        self.limits.fuel = Some(fuel);
+        self
+    }
+
+    pub fn with_max_depth(mut self, depth: usize) -> Self {
+        self.limits.depth = Some(depth);
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.limits.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_gc_threshold(mut self, threshold: usize) -> Self {
+        self.heap = Heap::new(threshold);
+        self
+    }
+
+    pub fn with_overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    pub fn with_registry(mut self, registry: Registry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    pub fn with_policy(mut self, policy: Policy) -> Self {
+        self.registry.set_policy(policy);
+        self
+    }
+
+    pub fn pc(&self) -> Option<usize> {
+        self.pc
+    }
+
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    pub fn stack(&self) -> &Stack {
+        &self.stack
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.heap.stats()
+    }
+
     pub fn run(
         &mut self,
         syms: Vec<(Box<str>, usize, Arity)>,
         ops: Vec<OpCode>,
+        consts: Vec<Constant>,
+    ) -> Result<Value, Error> {
+        self.constants.clear();
+        self.eval(&syms, &ops, &consts)
+    }
+
+    pub fn eval(
+        &mut self,
+        syms: &[(Box<str>, usize, Arity)],
+        ops: &[OpCode],
+        consts: &[Constant],
+    ) -> Result<Value, Error> {
+        self.load_constants(consts)?;
+        let pc = self.enter(syms)?;
+        self.invoke(pc, ops)
+    }
+
+    pub fn load_constants(&mut self, consts: &[Constant]) -> Result<(), Error> {
+        //
+        // Build the cells that have not been loaded yet. Pairs only reference
+        // earlier cells, so they are always available.
+        //
+        consts.iter().skip(self.constants.len()).try_for_each(|v| {
+            let value = match v {
+                Constant::Immediate(v) => heap::Value::Immediate(*v),
+                Constant::Pair(car, cdr) => {
+                    let car = self.constant_handle(*car as usize)?;
+                    let cdr = self.constant_handle(*cdr as usize)?;
+                    heap::Value::Pair(car, cdr)
+                }
+                Constant::BigNum(v) => heap::Value::BigNum(BigInt::from_signed_bytes_le(v)),
+                Constant::String(v) => match Str::new(v.as_ref().into()) {
+                    Some(v) => heap::Value::String(v),
+                    None => heap::Value::Immediate(Immediate::Nil),
+                },
+                Constant::Symbol(v) => {
+                    heap::Value::Immediate(Immediate::Symbol(self.heap.intern(v)))
+                }
+            };
+            let handle = self.heap.alloc(value);
+            self.constants.push(Value::Heap(handle));
+            Ok(())
+        })
+    }
+
+    pub fn truncate_constants(&mut self, len: usize) {
+        self.constants.truncate(len);
+    }
+
+    pub fn invoke(&mut self, mut pc: usize, ops: &[OpCode]) -> Result<Value, Error> {
+        //
+        // Reset the budget.
+        //
+        self.budget = Budget::new(&self.limits);
+        //
+        // Push the initial return value.
+        //
+        self.stack.push(Value::Link(ops.len()));
+        //
+        // Execute the program up to completion, ignoring the yields and
+        // clearing the stack on error.
+        //
+        loop {
+            match self.execute(pc, ops, None) {
+                Ok(Stop::Finished) => break,
+                Ok(Stop::Paused(v) | Stop::Yielded(v)) => pc = v,
+                Err(error) => {
+                    self.handlers.clear();
+                    self.stack.clear();
+                    return Err(error);
+                }
+            }
+        }
+        //
+        // Done.
+        //
+        Ok(self.finish())
+    }
+
+    pub fn load(
+        &mut self,
+        syms: Vec<(Box<str>, usize, Arity)>,
+        ops: Vec<OpCode>,
+        consts: Vec<Constant>,
     ) -> Result<(), Error> {
+        //
+        // Reset the state of the machine.
+        //
+        self.handlers.clear();
+        self.stack.clear();
+        self.pc = None;
+        //
+        // Build the constants.
+        //
+        self.constants.clear();
+        self.load_constants(&consts)?;
+        //
+        // Enter the main function.
+        //
+        let pc = self.enter(&syms)?;
+        //
+        // Reset the budget and push the initial return value.
+        //
+        self.budget = Budget::new(&self.limits);
+        self.stack.push(Value::Link(ops.len()));
+        //
+        // Keep track of the program.
+        //
+        self.ops = ops;
+        self.pc = Some(pc);
+        //
+        // Done.
+        //
+        Ok(())
+    }
+
+    pub fn step(&mut self, n: usize) -> Result<Status, Error> {
+        self.proceed(Some(n))
+    }
+
+    pub fn resume(&mut self) -> Result<Status, Error> {
+        self.proceed(None)
+    }
+
+    fn proceed(&mut self, steps: Option<usize>) -> Result<Status, Error> {
+        //
+        // Make sure there is a program to run.
+        //
+        let Some(pc) = self.pc.take() else {
+            return Err(Error::NotRunning);
+        };
+        //
+        // Execute the program.
+        //
+        let ops = std::mem::take(&mut self.ops);
+        let result = self.execute(pc, &ops, steps);
+        self.ops = ops;
+        //
+        // Process the result.
+        //
+        match result {
+            Ok(Stop::Finished) => Ok(Status::Finished(self.finish())),
+            Ok(Stop::Paused(pc)) => {
+                self.pc = Some(pc);
+                Ok(Status::Running)
+            }
+            Ok(Stop::Yielded(pc)) => {
+                self.pc = Some(pc);
+                Ok(Status::Yielded(*self.stack.peek()))
+            }
+            Err(error) => {
+                self.handlers.clear();
+                self.stack.clear();
+                Err(error)
+            }
+        }
+    }
+
+    fn finish(&mut self) -> Value {
+        //
+        // Keep the result rooted until the next program finishes, so that the
+        // caller can still use it while the machine runs again.
+        //
+        let value = self.stack.pop();
+        self.result = Some(value);
+        value
+    }
+
+    pub fn display<'a>(&'a self, value: &'a Value) -> Printer<'a> {
+        Printer(&self.heap, value)
+    }
+
+    pub fn status(value: &Value) -> i32 {
+        //
+        // Map the result of a program to a process exit status: numbers within
+        // 0..=255 are used as is, T is 0, and anything else is 1.
+        //
+        match value {
+            Value::Immediate(Immediate::Number(v @ 0..=255)) => *v as i32,
+            Value::Immediate(Immediate::True) => 0,
+            _ => 1,
+        }
+    }
+
+    fn constant_handle(&self, index: usize) -> Result<heap::Handle, Error> {
+        match self.constants.get(index) {
+            Some(Value::Heap(v)) => Ok(*v),
+            _ => Err(Error::InvalidConstant(index)),
+        }
+    }
+
+    fn enter(&mut self, syms: &[(Box<str>, usize, Arity)]) -> Result<usize, Error> {
         //
         // Look-up the main function.
         //
         let main_fn = syms
             .iter()
-            .find_map(|(k, v, _)| (k.as_ref() == "main").then_some(v))
-            .copied();
+            .find_map(|(k, v, a)| (k.as_ref() == "main").then_some((*v, *a)));
         //
         // Make sure it exists.
         //
-        let Some(mut pc) = main_fn else {
+        let Some((pc, arity)) = main_fn else {
             return Err(Error::MainNotDefined);
         };
         //
-        // Push the initial return value.
+        // Pass the arguments if main declares parameters.
         //
-        self.stack.push(Value::Link(ops.len()));
+        match arity {
+            Arity::None => (),
+            Arity::Some(1) | Arity::All => {
+                let arguments = self.arguments.clone();
+                arguments.iter().rev().for_each(|v| {
+                    let value = self.string(v.as_bytes());
+                    self.stack.push(value);
+                });
+                self.stack
+                    .list(&mut self.heap, arguments.len())
+                    .map_err(|v| {
+                        Error::TypeMismatch(pc, OpCode::Lst(arguments.len()), Kind::Value, v)
+                    })?;
+            }
+            _ => return Err(Error::InvalidMainArity),
+        }
+        //
+        // Done.
+        //
+        Ok(pc)
+    }
+
+    fn execute(
+        &mut self,
+        mut pc: usize,
+        ops: &[OpCode],
+        mut steps: Option<usize>,
+    ) -> Result<Stop, Error> {
         //
         // Interpreter loop.
         //
@@ -51,55 +427,152 @@ impl VirtualMachine {
             // Check if we are done.
             //
             if pc >= ops.len() {
-                break;
+                return Ok(Stop::Finished);
+            }
+            //
+            // Check if we are out of steps.
+            //
+            if let Some(steps) = &mut steps {
+                if *steps == 0 {
+                    return Ok(Stop::Paused(pc));
+                }
+                *steps -= 1;
             }
             //
+            // Make sure we are within budget.
+            //
+            self.check_budget(pc)?;
+            //
+            // Collect the garbage if necessary.
+            //
+            if self.heap.should_collect() {
+                let roots = self.stack.iter().chain(self.constants.iter());
+                self.heap.collect(roots.chain(self.result.iter()));
+            }
+            //
+            // Grab the opcode.
+            //
+            let op = ops[pc];
+            //
             // Print trace.
             //
             if self.trace {
-                println!("---- {:?}", self.stack);
-                println!("{pc:04} {:?}", ops[pc]);
+                println!("---- {}", self.dump());
+                println!("{pc:04} {op:?}");
             }
             //
             // Execute the opcode.
             //
-            match ops[pc] {
+            match op {
                 //
                 // Arithmetics.
                 //
                 OpCode::Add => {
-                    let a = self.stack.pop().as_immediate().as_number();
-                    let b = self.stack.pop().as_immediate().as_number();
-                    let v = Value::from(Immediate::Number(a + b));
-                    self.stack.push(v);
+                    let (a, b) = self.pop_numbers(pc, op)?;
+                    self.push_arithmetic(pc, a, b, i64::checked_add, |a, b| a + b, |a, b| a + b)?;
                 }
                 OpCode::Sub => {
-                    let a = self.stack.pop().as_immediate().as_number();
-                    let b = self.stack.pop().as_immediate().as_number();
-                    let v = Value::from(Immediate::Number(a - b));
-                    self.stack.push(v);
+                    let (a, b) = self.pop_numbers(pc, op)?;
+                    self.push_arithmetic(pc, a, b, i64::checked_sub, |a, b| a - b, |a, b| a - b)?;
+                }
+                OpCode::Mul => {
+                    let (a, b) = self.pop_numbers(pc, op)?;
+                    self.push_arithmetic(pc, a, b, i64::checked_mul, |a, b| a * b, |a, b| a * b)?;
+                }
+                OpCode::Div => {
+                    let (a, b) = self.pop_numbers(pc, op)?;
+                    if b.is_zero() {
+                        return Err(Error::DivisionByZero(pc));
+                    }
+                    self.push_arithmetic(pc, a, b, i64::checked_div, |a, b| a / b, |a, b| a / b)?;
+                }
+                OpCode::Mod => {
+                    let (a, b) = self.pop_numbers(pc, op)?;
+                    if b.is_zero() {
+                        return Err(Error::DivisionByZero(pc));
+                    }
+                    self.push_arithmetic(pc, a, b, i64::checked_rem, |a, b| a % b, |a, b| a % b)?;
+                }
+                OpCode::Neg => {
+                    match self.pop_number(pc, op)? {
+                        //
+                        // Negate floats directly to preserve the signed zeros.
+                        //
+                        Number::Float(v) => self.stack.push(Value::from(Immediate::Float(-v))),
+                        a => {
+                            let z = Number::Fixed(0);
+                            self.push_arithmetic(
+                                pc,
+                                z,
+                                a,
+                                i64::checked_sub,
+                                |a, b| a - b,
+                                |a, b| a - b,
+                            )?;
+                        }
+                    }
                 }
                 OpCode::Ge => {
-                    let a = self.stack.pop().as_immediate().as_number();
-                    let b = self.stack.pop().as_immediate().as_number();
+                    let (a, b) = self.pop_numbers(pc, op)?;
                     self.stack.push(Value::from(Immediate::from(a >= b)));
                 }
                 OpCode::Gt => {
-                    let a = self.stack.pop().as_immediate().as_number();
-                    let b = self.stack.pop().as_immediate().as_number();
+                    let (a, b) = self.pop_numbers(pc, op)?;
                     self.stack.push(Value::from(Immediate::from(a > b)));
                 }
                 OpCode::Le => {
-                    let a = self.stack.pop().as_immediate().as_number();
-                    let b = self.stack.pop().as_immediate().as_number();
+                    let (a, b) = self.pop_numbers(pc, op)?;
                     self.stack.push(Value::from(Immediate::from(a <= b)));
                 }
                 OpCode::Lt => {
-                    let a = self.stack.pop().as_immediate().as_number();
-                    let b = self.stack.pop().as_immediate().as_number();
+                    let (a, b) = self.pop_numbers(pc, op)?;
                     self.stack.push(Value::from(Immediate::from(a < b)));
                 }
                 //
+                // Bitwise operations.
+                //
+                OpCode::Band => {
+                    let (a, b) = self.pop_integers(pc, op)?;
+                    self.push_bitwise(a, b, |a, b| a & b, |a, b| a & b);
+                }
+                OpCode::Bor => {
+                    let (a, b) = self.pop_integers(pc, op)?;
+                    self.push_bitwise(a, b, |a, b| a | b, |a, b| a | b);
+                }
+                OpCode::Bxor => {
+                    let (a, b) = self.pop_integers(pc, op)?;
+                    self.push_bitwise(a, b, |a, b| a ^ b, |a, b| a ^ b);
+                }
+                OpCode::Shl => {
+                    let a = self.pop_integer(pc, op)?;
+                    let b = self.pop_shift(pc, op)?;
+                    let result = match a {
+                        //
+                        // Shifting out significant bits is an overflow.
+                        //
+                        Number::Fixed(a) => match a.checked_shl(b).filter(|v| v >> b == a) {
+                            Some(v) => Number::Fixed(v),
+                            None if self.overflow == Overflow::Promote => {
+                                Number::Big(BigInt::from(a) << b)
+                            }
+                            None => return Err(Error::ArithmeticOverflow(pc)),
+                        },
+                        a => Number::Big(BigInt::from(a) << b),
+                    };
+                    let value = self.number(result);
+                    self.stack.push(value);
+                }
+                OpCode::Shr => {
+                    let a = self.pop_integer(pc, op)?;
+                    let b = self.pop_shift(pc, op)?;
+                    let result = match a {
+                        Number::Fixed(a) => Number::Fixed(a >> b),
+                        a => Number::Big(BigInt::from(a) >> b),
+                    };
+                    let value = self.number(result);
+                    self.stack.push(value);
+                }
+                //
                 // Logics.
                 //
                 OpCode::And => {
@@ -110,12 +583,14 @@ impl VirtualMachine {
                 OpCode::Equ => {
                     let a = self.stack.pop();
                     let b = self.stack.pop();
-                    self.stack.push(Value::Immediate((a == b).into()));
+                    let r = self.heap.equal(&a, &b);
+                    self.stack.push(Value::Immediate(r.into()));
                 }
                 OpCode::Neq => {
                     let a = self.stack.pop();
                     let b = self.stack.pop();
-                    self.stack.push(Value::Immediate((a != b).into()));
+                    let r = !self.heap.equal(&a, &b);
+                    self.stack.push(Value::Immediate(r.into()));
                 }
                 OpCode::Not => {
                     let a = matches!(self.stack.pop(), Value::Immediate(Immediate::Nil));
@@ -131,12 +606,9 @@ impl VirtualMachine {
                 //
                 OpCode::Car => {
                     let result = match self.stack.pop() {
-                        Value::Heap(value) => match value.as_ref() {
-                            heap::Value::Pair(value, _) => match value.as_ref() {
-                                heap::Value::Closure(v) => Value::Closure(v.clone()),
-                                heap::Value::Immediate(v) => Value::Immediate(*v),
-                                _ => Value::Heap(value.clone()),
-                            },
+                        Value::Heap(value) => match self.heap.get(value) {
+                            heap::Value::Pair(value, _) => self.heap.load(*value),
+                            heap::Value::String(v) => Immediate::Char(v.first()).into(),
                             _ => Immediate::Nil.into(),
                         },
                         _ => Immediate::Nil.into(),
@@ -145,11 +617,11 @@ impl VirtualMachine {
                 }
                 OpCode::Cdr => {
                     let result = match self.stack.pop() {
-                        Value::Heap(value) => match value.as_ref() {
-                            heap::Value::Pair(_, value) => match value.as_ref() {
-                                heap::Value::Closure(v) => Value::Closure(v.clone()),
-                                heap::Value::Immediate(v) => Value::Immediate(*v),
-                                _ => Value::Heap(value.clone()),
+                        Value::Heap(value) => match self.heap.get(value) {
+                            heap::Value::Pair(_, value) => self.heap.load(*value),
+                            heap::Value::String(v) => match v.rest() {
+                                Some(v) => Value::Heap(self.heap.alloc(heap::Value::String(v))),
+                                None => Immediate::Nil.into(),
                             },
                             _ => Immediate::Nil.into(),
                         },
@@ -158,64 +630,86 @@ impl VirtualMachine {
                     self.stack.push(result);
                 }
                 OpCode::Cons => {
-                    let (a, b) = match (self.stack.pop(), self.stack.pop()) {
-                        (Value::Closure(a), Value::Closure(b)) => {
-                            let a = Rc::new(heap::Value::Closure(a));
-                            let b = Rc::new(heap::Value::Closure(b));
-                            (a, b)
-                        }
-                        (Value::Closure(a), Value::Heap(b)) => {
-                            let a = Rc::new(heap::Value::Closure(a));
-                            (a, b.clone())
-                        }
-                        (Value::Closure(a), Value::Immediate(b)) => {
-                            let a = Rc::new(heap::Value::Closure(a));
-                            let b = Rc::new(heap::Value::Immediate(b));
-                            (a, b)
-                        }
-                        (Value::Heap(a), Value::Closure(b)) => {
-                            let b = Rc::new(heap::Value::Closure(b));
-                            (a.clone(), b)
-                        }
-                        (Value::Heap(a), Value::Heap(b)) => (a.clone(), b.clone()),
-                        (Value::Heap(a), Value::Immediate(b)) => {
-                            let b = Rc::new(heap::Value::Immediate(b));
-                            (a.clone(), b)
-                        }
-                        (Value::Immediate(a), Value::Closure(b)) => {
-                            let a = Rc::new(heap::Value::Immediate(a));
-                            let b = Rc::new(heap::Value::Closure(b));
-                            (a, b)
-                        }
-                        (Value::Immediate(a), Value::Heap(b)) => {
-                            let a = Rc::new(heap::Value::Immediate(a));
-                            (a, b.clone())
-                        }
-                        (Value::Immediate(a), Value::Immediate(b)) => {
-                            let a = Rc::new(heap::Value::Immediate(a));
-                            let b = Rc::new(heap::Value::Immediate(b));
-                            (a, b)
-                        }
-                        _ => panic!("Return link cannot be pushed to the heap"),
-                    };
-                    self.stack
-                        .push(Value::Heap(Rc::new(heap::Value::Pair(a, b))));
+                    let a = self.pop_handle(pc, op)?;
+                    let b = self.pop_handle(pc, op)?;
+                    let v = self.heap.alloc(heap::Value::Pair(a, b));
+                    self.stack.push(Value::Heap(v));
                 }
                 //
-                // String operation.
+                // String operations.
                 //
                 OpCode::Str => {
                     let value = match self.stack.pop() {
-                        Value::Heap(value) => match value.as_ref() {
-                            heap::Value::Immediate(imm) => Self::immediate_to_string(*imm),
-                            heap::Value::Pair(..) => Value::Heap(value.clone()),
+                        Value::Heap(value) => match self.heap.get(value) {
+                            heap::Value::BigNum(v) => self.string(v.to_string().as_bytes()),
+                            heap::Value::Immediate(imm) => self.immediate_to_string(*imm),
+                            heap::Value::Pair(..) | heap::Value::String(_) => Value::Heap(value),
                             _ => Value::Immediate(Immediate::Nil),
                         },
-                        Value::Immediate(imm) => Self::immediate_to_string(imm),
+                        Value::Immediate(imm) => self.immediate_to_string(imm),
                         _ => Value::Immediate(Immediate::Nil),
                     };
                     self.stack.push(value);
                 }
+                OpCode::Len => {
+                    let a = self.stack.pop();
+                    let n = heap::char_indices(&self.string_arg(pc, op, &a)?).count();
+                    self.stack
+                        .push(Value::Immediate(Immediate::Number(n as i64)));
+                }
+                OpCode::Cat => {
+                    let a = self.stack.pop();
+                    let b = self.stack.pop();
+                    let bytes =
+                        [self.string_arg(pc, op, &a)?, self.string_arg(pc, op, &b)?].concat();
+                    let value = self.string(&bytes);
+                    self.stack.push(value);
+                }
+                OpCode::Substr => {
+                    let a = self.stack.pop();
+                    let b = self.pop_fixed(pc, op)?;
+                    let c = self.pop_fixed(pc, op)?;
+                    //
+                    // Find the character boundaries.
+                    //
+                    let bytes = self.string_arg(pc, op, &a)?;
+                    let offsets: Vec<_> = heap::char_indices(&bytes)
+                        .map(|(i, _)| i)
+                        .chain(std::iter::once(bytes.len()))
+                        .collect();
+                    //
+                    // Clamp the bounds to the string.
+                    //
+                    let end = c.clamp(0, offsets.len() as i64 - 1) as usize;
+                    let start = b.clamp(0, end as i64) as usize;
+                    let bytes = bytes[offsets[start]..offsets[end]].to_vec();
+                    let value = self.string(&bytes);
+                    self.stack.push(value);
+                }
+                OpCode::Cmp => {
+                    let a = self.stack.pop();
+                    let b = self.stack.pop();
+                    let r = self
+                        .string_arg(pc, op, &a)?
+                        .cmp(&self.string_arg(pc, op, &b)?);
+                    self.stack
+                        .push(Value::Immediate(Immediate::Number(r as i64)));
+                }
+                OpCode::Find => {
+                    let a = self.stack.pop();
+                    let b = self.stack.pop();
+                    let haystack = self.string_arg(pc, op, &a)?;
+                    let needle = self.string_arg(pc, op, &b)?;
+                    let index = match needle.len() {
+                        0 => Some(0),
+                        n => haystack.windows(n).position(|v| v == needle.as_ref()),
+                    };
+                    let r = index.map_or(Immediate::Nil, |v| {
+                        let n = heap::char_indices(&haystack[..v]).count();
+                        Immediate::Number(n as i64)
+                    });
+                    self.stack.push(Value::Immediate(r));
+                }
                 //
                 // Predicates.
                 //
@@ -223,13 +717,23 @@ impl VirtualMachine {
                     let r = matches!(self.stack.pop(), Value::Immediate(Immediate::Char(_)));
                     self.stack.push(Value::Immediate(r.into()));
                 }
+                OpCode::IsFlt => {
+                    let r = matches!(self.stack.pop(), Value::Immediate(Immediate::Float(_)));
+                    self.stack.push(Value::Immediate(r.into()));
+                }
                 OpCode::IsNum => {
-                    let r = matches!(self.stack.pop(), Value::Immediate(Immediate::Number(_)));
+                    let r = matches!(
+                        self.heap.kind(&self.stack.pop()),
+                        Kind::BigNum | Kind::Float | Kind::Number
+                    );
                     self.stack.push(Value::Immediate(r.into()));
                 }
                 OpCode::IsLst => {
                     let r = match self.stack.pop() {
-                        Value::Heap(value) => matches!(value.as_ref(), heap::Value::Pair(..)),
+                        Value::Heap(value) => matches!(
+                            self.heap.get(value),
+                            heap::Value::Pair(..) | heap::Value::String(_)
+                        ),
                         Value::Immediate(Immediate::Nil) => true,
                         _ => false,
                     };
@@ -265,16 +769,29 @@ impl VirtualMachine {
                         //
                         // Unpack the closure.
                         //
-                        let (argpak, paklen) = self.stack.unpack(v);
+                        let (argpak, paklen) = self
+                            .stack
+                            .unpack(&self.heap, v)
+                            .map_err(|v| Error::TypeMismatch(pc, op, Kind::Closure, v))?;
                         //
                         // Decode the funcall.
                         //
-                        match self.stack.pop().as_immediate() {
-                            Immediate::Funcall(addr, Arity::All) => {
+                        match self.stack.pop() {
+                            Value::Immediate(Immediate::Funcall(addr, Arity::None)) => {
+                                //
+                                // Push the return link and go to the funcall address.
+                                //
+                                self.stack.push(Value::Link(pc + 1));
+                                pc = addr as usize;
+                                continue;
+                            }
+                            Value::Immediate(Immediate::Funcall(addr, Arity::All)) => {
                                 //
                                 // Collect the arguments into a list.
                                 //
-                                self.stack.list(argcnt);
+                                self.stack
+                                    .list(&mut self.heap, argcnt)
+                                    .map_err(|v| Error::TypeMismatch(pc, op, Kind::Value, v))?;
                                 //
                                 // Push the return link and go to the funcall address.
                                 //
@@ -282,14 +799,21 @@ impl VirtualMachine {
                                 pc = addr as usize;
                                 continue;
                             }
-                            Immediate::Funcall(addr, arity @ Arity::Some(argexp)) => {
+                            Value::Immediate(Immediate::Funcall(
+                                addr,
+                                arity @ Arity::Some(argexp),
+                            )) => {
                                 //
                                 // Pack in case of currying.
                                 //
                                 if argcnt + argpak < argexp as usize {
                                     let imm = Immediate::Funcall(addr, arity);
                                     self.stack.push(Value::Immediate(imm));
-                                    self.stack.pack(argcnt + argpak, argcnt + paklen);
+                                    self.stack.pack(
+                                        &mut self.heap,
+                                        argcnt + argpak,
+                                        argcnt + paklen,
+                                    );
                                 }
                                 //
                                 // Push the return link and go to the funcall address.
@@ -300,14 +824,21 @@ impl VirtualMachine {
                                     continue;
                                 }
                             }
-                            Immediate::Funcall(addr, arity @ Arity::SomeWithRem(argexp)) => {
+                            Value::Immediate(Immediate::Funcall(
+                                addr,
+                                arity @ Arity::SomeWithRem(argexp),
+                            )) => {
                                 //
                                 // Pack in case of currying.
                                 //
                                 if argcnt + argpak < argexp as usize {
                                     let imm = Immediate::Funcall(addr, arity);
                                     self.stack.push(Value::Immediate(imm));
-                                    self.stack.pack(argcnt + argpak, argcnt + paklen);
+                                    self.stack.pack(
+                                        &mut self.heap,
+                                        argcnt + argpak,
+                                        argcnt + paklen,
+                                    );
                                 }
                                 //
                                 // Push the return link and go to the funcall address.
@@ -325,7 +856,9 @@ impl VirtualMachine {
                                     //
                                     // Collect the remaining arguments into a list.
                                     //
-                                    self.stack.list(argcnt + argpak - argexp as usize);
+                                    self.stack
+                                        .list(&mut self.heap, argcnt + argpak - argexp as usize)
+                                        .map_err(|v| Error::TypeMismatch(pc, op, Kind::Value, v))?;
                                     //
                                     // Rotate the arguments.
                                     //
@@ -338,33 +871,47 @@ impl VirtualMachine {
                                     continue;
                                 }
                             }
-                            Immediate::Syscall(index, argexp) => {
+                            Value::Immediate(Immediate::Syscall(index, argexp)) => {
                                 //
                                 // Pack in case of currying.
                                 //
                                 if argcnt + argpak < argexp as usize {
                                     let imm = Immediate::Syscall(index, argexp);
                                     self.stack.push(Value::Immediate(imm));
-                                    self.stack.pack(argcnt + argpak, argcnt + paklen);
+                                    self.stack.pack(
+                                        &mut self.heap,
+                                        argcnt + argpak,
+                                        argcnt + paklen,
+                                    );
                                 }
                                 //
                                 // Push the return link and go to the funcall address.
                                 //
                                 else {
-                                    let values = self.stack.slice_n(argexp as usize);
-                                    let res = syscalls::call(index, values);
-                                    self.stack.drop(argexp as usize);
-                                    self.stack.push(res);
+                                    self.syscall(pc, index, argexp as usize)?;
                                 }
                             }
-                            _ => panic!("Expected a funcall or syscall"),
+                            v => {
+                                let kind = self.heap.kind(&v);
+                                return Err(Error::TypeMismatch(pc, op, Kind::Callable, kind));
+                            }
                         }
                     }
+                    Value::Immediate(Immediate::Funcall(addr, Arity::None)) => {
+                        //
+                        // Push the return link and go to the funcall address.
+                        //
+                        self.stack.push(Value::Link(pc + 1));
+                        pc = addr as usize;
+                        continue;
+                    }
                     Value::Immediate(Immediate::Funcall(addr, Arity::All)) => {
                         //
                         // Collect the arguments into a list.
                         //
-                        self.stack.list(argcnt);
+                        self.stack
+                            .list(&mut self.heap, argcnt)
+                            .map_err(|v| Error::TypeMismatch(pc, op, Kind::Value, v))?;
                         //
                         // Push the return link and go to the funcall address.
                         //
@@ -379,7 +926,7 @@ impl VirtualMachine {
                         if argcnt < argexp as usize {
                             let imm = Immediate::Funcall(addr, arity);
                             self.stack.push(Value::Immediate(imm));
-                            self.stack.pack(argcnt, argcnt + 1);
+                            self.stack.pack(&mut self.heap, argcnt, argcnt + 1);
                         }
                         //
                         // Push the return link and go to the funcall address.
@@ -400,7 +947,7 @@ impl VirtualMachine {
                         if argcnt < argexp as usize {
                             let imm = Immediate::Funcall(addr, arity);
                             self.stack.push(Value::Immediate(imm));
-                            self.stack.pack(argcnt, argcnt + 1);
+                            self.stack.pack(&mut self.heap, argcnt, argcnt + 1);
                         }
                         //
                         // Push the return link and go to the funcall address.
@@ -418,7 +965,9 @@ impl VirtualMachine {
                             //
                             // Collect the remaining arguments into a list.
                             //
-                            self.stack.list(argcnt - argexp as usize);
+                            self.stack
+                                .list(&mut self.heap, argcnt - argexp as usize)
+                                .map_err(|v| Error::TypeMismatch(pc, op, Kind::Value, v))?;
                             //
                             // Rotate the arguments.
                             //
@@ -438,22 +987,60 @@ impl VirtualMachine {
                         if argcnt < argexp as usize {
                             let imm = Immediate::Syscall(index, argexp);
                             self.stack.push(Value::Immediate(imm));
-                            self.stack.pack(argcnt, argcnt + 1);
+                            self.stack.pack(&mut self.heap, argcnt, argcnt + 1);
                         }
                         //
                         // Push the return link and go to the funcall address.
                         //
                         else {
-                            let values = self.stack.slice_n(argexp as usize);
-                            let res = syscalls::call(index, values);
-                            self.stack.drop(argexp as usize);
-                            self.stack.push(res);
+                            self.syscall(pc, index, argexp as usize)?;
                         }
                     }
-                    _ => panic!("Expected a closure, funcall or syscall"),
+                    v => {
+                        let kind = self.heap.kind(&v);
+                        return Err(Error::TypeMismatch(pc, op, Kind::Callable, kind));
+                    }
                 },
                 OpCode::Ret => {
-                    pc = self.stack.unlink().link();
+                    pc = match self.stack.unlink() {
+                        Value::Link(v) => v,
+                        v => {
+                            let kind = self.heap.kind(&v);
+                            return Err(Error::TypeMismatch(pc, op, Kind::Link, kind));
+                        }
+                    };
+                    continue;
+                }
+                OpCode::Yield => return Ok(Stop::Yielded(pc + 1)),
+                //
+                // Exceptions.
+                //
+                OpCode::Try(v) => {
+                    let target = (pc as isize + v) as usize;
+                    self.handlers.push((self.stack.len(), target));
+                }
+                OpCode::EndTry => {
+                    self.handlers.pop();
+                }
+                OpCode::Throw => {
+                    let value = self.stack.pop();
+                    //
+                    // Get the nearest handler frame.
+                    //
+                    let Some((depth, target)) = self.handlers.pop() else {
+                        let value = Printer(&self.heap, &value).to_string();
+                        return Err(Error::UncaughtException(pc, value.into()));
+                    };
+                    //
+                    // Unwind the stack, including the return links, up to the
+                    // handler and push the thrown value.
+                    //
+                    self.stack.truncate(depth);
+                    self.stack.push(value);
+                    //
+                    // Jump to the handler.
+                    //
+                    pc = target;
                     continue;
                 }
                 //
@@ -461,8 +1048,15 @@ impl VirtualMachine {
                 //
                 OpCode::Dup(v) => self.stack.dup(v),
                 OpCode::Get(v) => self.stack.get(v),
-                OpCode::Lst(n) => self.stack.list(n),
-                OpCode::Pak(v) => self.stack.pack(0, v),
+                OpCode::Ldc(v) => {
+                    let value = self.heap.load(self.constant_handle(v)?);
+                    self.stack.push(value);
+                }
+                OpCode::Lst(n) => self
+                    .stack
+                    .list(&mut self.heap, n)
+                    .map_err(|v| Error::TypeMismatch(pc, op, Kind::Value, v))?,
+                OpCode::Pak(v) => self.stack.pack(&mut self.heap, 0, v),
                 OpCode::Pop(v) => self.stack.drop(v),
                 OpCode::Psh(v) => self.stack.push(Value::from(v)),
                 OpCode::Rot(n) => self.stack.rotate(n),
@@ -474,75 +1068,282 @@ impl VirtualMachine {
             //
             pc += 1;
         }
+    }
+}
+
+//
+// Helpers.
+//
+
+impl VirtualMachine {
+    fn pop_fixed(&mut self, pc: usize, op: OpCode) -> Result<i64, Error> {
+        match self.stack.pop() {
+            Value::Immediate(Immediate::Number(v)) => Ok(v),
+            v => Err(Error::TypeMismatch(
+                pc,
+                op,
+                Kind::Number,
+                self.heap.kind(&v),
+            )),
+        }
+    }
+
+    fn pop_number(&mut self, pc: usize, op: OpCode) -> Result<Number, Error> {
+        let value = self.stack.pop();
+        match &value {
+            Value::Immediate(Immediate::Number(v)) => Ok(Number::Fixed(*v)),
+            Value::Heap(v) => match self.heap.get(*v) {
+                heap::Value::BigNum(v) => Ok(Number::Big(v.clone())),
+                _ => Err(Error::TypeMismatch(
+                    pc,
+                    op,
+                    Kind::Number,
+                    self.heap.kind(&value),
+                )),
+            },
+            Value::Immediate(Immediate::Float(v)) => Ok(Number::Float(*v)),
+            _ => Err(Error::TypeMismatch(
+                pc,
+                op,
+                Kind::Number,
+                self.heap.kind(&value),
+            )),
+        }
+    }
+
+    fn pop_integer(&mut self, pc: usize, op: OpCode) -> Result<Number, Error> {
+        match self.pop_number(pc, op)? {
+            Number::Float(_) => Err(Error::TypeMismatch(pc, op, Kind::Number, Kind::Float)),
+            v => Ok(v),
+        }
+    }
+
+    fn pop_shift(&mut self, pc: usize, op: OpCode) -> Result<u32, Error> {
+        let value = self.pop_fixed(pc, op)?;
+        u32::try_from(value)
+            .ok()
+            .filter(|v| *v < i64::BITS)
+            .ok_or(Error::InvalidShift(pc, value))
+    }
+
+    fn pop_integers(&mut self, pc: usize, op: OpCode) -> Result<(Number, Number), Error> {
+        let a = self.pop_integer(pc, op)?;
+        let b = self.pop_integer(pc, op)?;
+        Ok((a, b))
+    }
+
+    fn pop_numbers(&mut self, pc: usize, op: OpCode) -> Result<(Number, Number), Error> {
+        let a = self.pop_number(pc, op)?;
+        let b = self.pop_number(pc, op)?;
+        Ok((a, b))
+    }
+
+    fn push_arithmetic(
+        &mut self,
+        pc: usize,
+        a: Number,
+        b: Number,
+        fixed: impl Fn(i64, i64) -> Option<i64>,
+        big: impl Fn(BigInt, BigInt) -> BigInt,
+        float: impl Fn(f64, f64) -> f64,
+    ) -> Result<(), Error> {
+        let result = match (a, b) {
+            //
+            // Floating-point arithmetics, if either operand is a float.
+            //
+            (Number::Float(a), b) => Number::Float(float(a, b.into())),
+            (a, Number::Float(b)) => Number::Float(float(a.into(), b)),
+            //
+            // Fixed-width arithmetics, subject to the overflow policy.
+            //
+            (Number::Fixed(a), Number::Fixed(b)) => match fixed(a, b) {
+                Some(v) => Number::Fixed(v),
+                None if self.overflow == Overflow::Promote => Number::Big(big(a.into(), b.into())),
+                None => return Err(Error::ArithmeticOverflow(pc)),
+            },
+            //
+            // Arbitrary-precision arithmetics.
+            //
+            (a, b) => Number::Big(big(a.into(), b.into())),
+        };
+        let value = self.number(result);
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn push_bitwise(
+        &mut self,
+        a: Number,
+        b: Number,
+        fixed: impl Fn(i64, i64) -> i64,
+        big: impl Fn(BigInt, BigInt) -> BigInt,
+    ) {
+        let result = match (a, b) {
+            (Number::Fixed(a), Number::Fixed(b)) => Number::Fixed(fixed(a, b)),
+            (a, b) => Number::Big(big(a.into(), b.into())),
+        };
+        let value = self.number(result);
+        self.stack.push(value);
+    }
+
+    fn pop_handle(&mut self, pc: usize, op: OpCode) -> Result<heap::Handle, Error> {
+        let value = self.stack.pop();
+        match self.heap.store(value) {
+            Some(v) => Ok(v),
+            None => Err(Error::TypeMismatch(pc, op, Kind::Value, Kind::Link)),
+        }
+    }
+
+    fn number(&mut self, value: Number) -> Value {
+        match value {
+            Number::Fixed(v) => Value::Immediate(Immediate::Number(v)),
+            //
+            // Demote the number if it fits in a fixed-width integer.
+            //
+            Number::Big(v) => match i64::try_from(&v) {
+                Ok(v) => Value::Immediate(Immediate::Number(v)),
+                Err(_) => Value::Heap(self.heap.alloc(heap::Value::BigNum(v))),
+            },
+            Number::Float(v) => Value::Immediate(Immediate::Float(v)),
+        }
+    }
+
+    fn check_budget(&mut self, pc: usize) -> Result<(), Error> {
+        //
+        // Consume the fuel.
+        //
+        if let Some(fuel) = &mut self.budget.fuel {
+            if *fuel == 0 {
+                return Err(Error::FuelExhausted(pc));
+            }
+            *fuel -= 1;
+        }
+        //
+        // Check the depth of the stack.
+        //
+        if self.limits.depth.is_some_and(|v| self.stack.len() > v) {
+            return Err(Error::StackOverflow(pc));
+        }
         //
-        // Print the stack.
+        // Check the deadline, periodically as getting the time is not free.
         //
-        println!("{:?}", self.stack);
+        self.budget.ticks += 1;
+        if self.budget.ticks.is_multiple_of(DEADLINE_INTERVAL)
+            && self.budget.deadline.is_some_and(|v| Instant::now() >= v)
+        {
+            return Err(Error::DeadlineExceeded(pc));
+        }
         //
         // Done.
         //
         Ok(())
     }
-}
 
-//
-// Helpers.
-//
+    fn syscall(&mut self, pc: usize, index: u32, argexp: usize) -> Result<(), Error> {
+        //
+        // Call the host function, if the policy permits it.
+        //
+        let values = self.stack.slice_n(argexp);
+        let res = self.registry.call(&mut self.heap, pc, index, values)?;
+        //
+        // Replace the arguments with the result.
+        //
+        self.stack.drop(argexp);
+        self.stack.push(res);
+        Ok(())
+    }
 
-impl VirtualMachine {
-    fn immediate_to_string(imm: Immediate) -> Value {
+    fn string_arg(&self, pc: usize, op: OpCode, value: &Value) -> Result<Cow<'_, [u8]>, Error> {
+        let result = match value {
+            Value::Heap(v) => self.heap.bytes(*v),
+            Value::Immediate(Immediate::Nil) => Some(Cow::Borrowed(&[][..])),
+            _ => None,
+        };
+        result.ok_or_else(|| Error::TypeMismatch(pc, op, Kind::String, self.heap.kind(value)))
+    }
+
+    fn immediate_to_string(&mut self, imm: Immediate) -> Value {
         match imm {
-            Immediate::True => {
-                let e = heap::Value::Pair(
-                    Rc::new(heap::Value::Immediate(Immediate::Char('T' as u8))),
-                    Rc::new(heap::Value::Immediate(Immediate::Nil)),
-                );
-                Value::Heap(Rc::new(e))
-            }
-            Immediate::Char(v) => {
-                let e = heap::Value::Pair(
-                    Rc::new(heap::Value::Immediate(Immediate::Char(v))),
-                    Rc::new(heap::Value::Immediate(Immediate::Nil)),
-                );
-                Value::Heap(Rc::new(e))
-            }
-            Immediate::Number(v) => {
-                //
-                // Stringify the number.
-                //
-                let v = format!("{v}");
-                //
-                // Convert it to a string (list of chars).
-                //
-                let value = v.bytes().rev().fold(
-                    Rc::new(heap::Value::Immediate(Immediate::Nil)),
-                    |acc, v| {
-                        let v = Rc::new(heap::Value::Immediate(Immediate::Char(v)));
-                        Rc::new(heap::Value::Pair(v, acc))
-                    },
-                );
-                //
-                // Done.
-                //
-                Value::Heap(value)
-            }
+            Immediate::True => self.string(b"T"),
+            Immediate::Char(v) => self.string(v.encode_utf8(&mut [0; 4]).as_bytes()),
+            Immediate::Number(v) => self.string(v.to_string().as_bytes()),
+            Immediate::Float(v) => self.string(format!("{v:?}").as_bytes()),
             Immediate::Symbol(v) => {
-                //
-                // Convert it to a string (list of chars).
-                //
-                let value = v.into_iter().filter(|v| *v != 0).rev().fold(
-                    Rc::new(heap::Value::Immediate(Immediate::Nil)),
-                    |acc, v| {
-                        let v = Rc::new(heap::Value::Immediate(Immediate::Char(v)));
-                        Rc::new(heap::Value::Pair(v, acc))
-                    },
-                );
-                //
-                // Done.
-                //
-                Value::Heap(value)
+                let name = self.heap.symbol(v).to_owned();
+                self.string(name.as_bytes())
             }
             _ => Value::Immediate(Immediate::Nil),
         }
     }
+
+    fn string(&mut self, v: &[u8]) -> Value {
+        match Str::new(v.into()) {
+            Some(v) => Value::Heap(self.heap.alloc(heap::Value::String(v))),
+            None => Value::Immediate(Immediate::Nil),
+        }
+    }
+
+    fn dump(&self) -> String {
+        let values: Vec<_> = self.stack.iter().map(|v| Dump(&self.heap, v)).collect();
+        format!("Stack({values:?})")
+    }
+}
+
+//
+// Number.
+//
+
+#[derive(Clone, Debug)]
+enum Number {
+    Fixed(i64),
+    Big(BigInt),
+    Float(f64),
+}
+
+impl Number {
+    fn is_zero(&self) -> bool {
+        match self {
+            Number::Fixed(v) => *v == 0,
+            Number::Big(v) => v.sign() == Sign::NoSign,
+            Number::Float(v) => *v == 0.0,
+        }
+    }
+}
+
+impl From<Number> for BigInt {
+    fn from(value: Number) -> Self {
+        match value {
+            Number::Fixed(v) => v.into(),
+            Number::Big(v) => v,
+            Number::Float(v) => BigInt::from_f64(v).unwrap_or_default(),
+        }
+    }
+}
+
+impl From<Number> for f64 {
+    fn from(value: Number) -> Self {
+        match value {
+            Number::Fixed(v) => v as f64,
+            Number::Big(v) => v.to_f64().unwrap_or(f64::NAN),
+            Number::Float(v) => v,
+        }
+    }
+}
+
+impl PartialEq for Number {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Number::Fixed(a), Number::Fixed(b)) => a.partial_cmp(b),
+            (Number::Fixed(a), Number::Big(b)) => BigInt::from(*a).partial_cmp(b),
+            (Number::Big(a), Number::Fixed(b)) => a.partial_cmp(&BigInt::from(*b)),
+            (Number::Big(a), Number::Big(b)) => a.partial_cmp(b),
+            (a, b) => f64::from(a.clone()).partial_cmp(&f64::from(b.clone())),
+        }
+    }
 }